@@ -0,0 +1,506 @@
+use super::keypool::{KeyOutcome, KeyPool};
+use super::{BackendError, RetryPolicy, SentimentBackend};
+use crate::labels::LabelMapping;
+use crate::progress;
+use crate::render::Tint;
+use crate::report::{parse_batch_response, parse_sentiment_response, SentimentReport};
+use reqwest::blocking::{Client, Response};
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+use serde_json::json;
+use std::thread;
+use std::time::Duration;
+
+/// Default endpoint, model ids are appended to it.
+pub static HF_MODELS_URL: &str = "https://api-inference.huggingface.co/models";
+
+/// Remote inference through the HuggingFace Inference API.
+pub struct HuggingFaceBackend {
+    client: Client,
+    model_id: String,
+    model_path: String,
+    /// One key, or several used in turn.
+    keys: KeyPool,
+    label_mapping: LabelMapping,
+    retry_policy: RetryPolicy,
+    batch_size: usize,
+}
+
+/// Outcome of a failed attempt: either worth retrying (possibly after a wait
+/// imposed by the server) or final.
+enum AttemptError {
+    Transient {
+        error: BackendError,
+        wait: Option<Duration>,
+    },
+    /// The key was at fault and another one of the pool can be used straight
+    /// away, which does not count as a retry.
+    Rotate(BackendError),
+    Fatal(BackendError),
+}
+
+impl HuggingFaceBackend {
+    pub fn new(
+        client: Client,
+        endpoint: &str,
+        model_id: &str,
+        keys: KeyPool,
+        label_mapping: LabelMapping,
+        retry_policy: RetryPolicy,
+        batch_size: usize,
+    ) -> Self {
+        HuggingFaceBackend {
+            client,
+            model_id: model_id.to_string(),
+            model_path: format!("{}/{model_id}", endpoint.trim_end_matches('/')),
+            keys,
+            label_mapping,
+            retry_policy,
+            batch_size,
+        }
+    }
+
+    /// Posts `inputs` (a string or an array of strings) once and returns the
+    /// body of a successful response.
+    fn attempt(&self, inputs: &serde_json::Value) -> Result<String, AttemptError> {
+        let payload = if self.retry_policy.wait_for_model {
+            json!({"inputs": inputs, "options": {"wait_for_model": true}})
+        } else {
+            json!({"inputs": inputs})
+        };
+        let Some((key_index, api_key)) = self.keys.pick() else {
+            return Err(AttemptError::Fatal(BackendError::Status {
+                code: 401,
+                message: "every API key of the pool was rejected".to_string(),
+            }));
+        };
+        let response = self
+            .client
+            .post(&self.model_path)
+            .bearer_auth(api_key)
+            .json(&payload)
+            .send()
+            .map_err(|e| AttemptError::Transient {
+                error: BackendError::Transport(e.to_string()),
+                wait: None,
+            })?;
+
+        let status = response.status();
+        let retry_after = retry_after(&response);
+        let body = response.text().map_err(|e| AttemptError::Transient {
+            error: BackendError::Transport(e.to_string()),
+            wait: None,
+        })?;
+        if status.is_success() {
+            self.keys.record(key_index, KeyOutcome::Success);
+            return Ok(body);
+        }
+
+        let error = BackendError::Status {
+            code: status.as_u16(),
+            message: error_message(&body),
+        };
+        let key_outcome = match status {
+            StatusCode::TOO_MANY_REQUESTS => KeyOutcome::RateLimited(retry_after),
+            StatusCode::UNAUTHORIZED | StatusCode::PAYMENT_REQUIRED => KeyOutcome::Disabled,
+            _ => KeyOutcome::Failure,
+        };
+        self.keys.record(key_index, key_outcome);
+        if matches!(
+            key_outcome,
+            KeyOutcome::RateLimited(_) | KeyOutcome::Disabled
+        ) && self.keys.has_available()
+        {
+            return Err(AttemptError::Rotate(error));
+        }
+        match status {
+            // The model is cold, the API tells us roughly how long loading takes.
+            StatusCode::SERVICE_UNAVAILABLE => Err(AttemptError::Transient {
+                error,
+                wait: estimated_time(&body).or(retry_after),
+            }),
+            StatusCode::TOO_MANY_REQUESTS => Err(AttemptError::Transient {
+                error,
+                wait: retry_after,
+            }),
+            StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::GATEWAY_TIMEOUT => Err(AttemptError::Transient { error, wait: None }),
+            _ => Err(AttemptError::Fatal(error)),
+        }
+    }
+}
+
+impl SentimentBackend for HuggingFaceBackend {
+    fn describe(&self) -> String {
+        format!(
+            "HuggingFace Inference API ({})\nLabel mapping: {}",
+            self.model_path,
+            self.label_mapping.describe()
+        )
+    }
+
+    fn model(&self) -> String {
+        self.model_id.clone()
+    }
+
+    fn labels(&self) -> Vec<String> {
+        self.label_mapping.labels()
+    }
+
+    fn analyze(&self, text: &str) -> Result<SentimentReport, BackendError> {
+        let body = self.request(&json!(text))?;
+        Ok(parse_sentiment_response(&body, &self.label_mapping)?)
+    }
+
+    /// Sends the texts `batch_size` at a time, one request per chunk.
+    ///
+    /// Every text gets its own result: a label set the mapping rejects only
+    /// fails that text, and a chunk the API refuses as a whole (typically
+    /// because of one bad input) is retried one text at a time.
+    fn analyze_batch(&self, texts: &[String]) -> Vec<Result<SentimentReport, BackendError>> {
+        let mut results = Vec::with_capacity(texts.len());
+        for chunk in texts.chunks(self.batch_size.max(1)) {
+            let chunk_results: Vec<_> = match self.request(&json!(chunk)) {
+                Ok(body) => match parse_batch_response(&body, &self.label_mapping) {
+                    Ok(reports) if reports.len() == chunk.len() => reports
+                        .into_iter()
+                        .map(|report| report.map_err(BackendError::from))
+                        .collect(),
+                    _ => chunk.iter().map(|text| self.analyze(text)).collect(),
+                },
+                Err(BackendError::Status { code, .. }) if is_input_rejection(code) => {
+                    chunk.iter().map(|text| self.analyze(text)).collect()
+                }
+                Err(error) => chunk.iter().map(|_| Err(error.clone())).collect(),
+            };
+            results.extend(chunk_results);
+        }
+        results
+    }
+
+    fn replace_api_key(&self, api_key: &str) -> bool {
+        self.keys.replace_disabled(api_key);
+        true
+    }
+
+    fn usage_report(&self) -> Option<String> {
+        self.keys.report()
+    }
+}
+
+impl HuggingFaceBackend {
+    /// `attempt` wrapped in the retry policy.
+    fn request(&self, inputs: &serde_json::Value) -> Result<String, BackendError> {
+        let mut retry = 0;
+        loop {
+            match self.attempt(inputs) {
+                Ok(body) => return Ok(body),
+                Err(AttemptError::Fatal(error)) => return Err(error),
+                Err(AttemptError::Rotate(error)) => {
+                    progress::note(&format!("{error}. Switching to another API key").yellow());
+                }
+                Err(AttemptError::Transient { error, .. })
+                    if retry >= self.retry_policy.max_retries =>
+                {
+                    return Err(error)
+                }
+                Err(AttemptError::Transient { error, wait }) => {
+                    let delay = match wait {
+                        Some(wait) => self.retry_policy.capped(wait),
+                        None => self.retry_policy.backoff(retry),
+                    };
+                    retry += 1;
+                    progress::note(
+                        &format!(
+                            "{error}. Retrying in {:.1}s ({retry}/{})",
+                            delay.as_secs_f64(),
+                            self.retry_policy.max_retries
+                        )
+                        .yellow(),
+                    );
+                    thread::sleep(delay);
+                }
+            }
+        }
+    }
+}
+
+/// Client errors caused by the content of a request rather than by the key.
+fn is_input_rejection(code: u16) -> bool {
+    (400..500).contains(&code) && ![401, 403, 429].contains(&code)
+}
+
+/// The Inference API reports errors as `{"error": "..."}`.
+fn error_message(body: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|value| {
+            value
+                .get("error")
+                .and_then(|e| e.as_str())
+                .map(String::from)
+        })
+        .unwrap_or_default()
+}
+
+/// `Retry-After` in its delay-seconds form (the HTTP-date form is not used by the API).
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// A loading model answers 503 with `{"error": ..., "estimated_time": <seconds>}`.
+fn estimated_time(body: &str) -> Option<Duration> {
+    serde_json::from_str::<serde_json::Value>(body)
+        .ok()?
+        .get("estimated_time")?
+        .as_f64()
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+        .map(Duration::from_secs_f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::keypool::KeyRotation;
+    use crate::testing::{serve, serve_with_headers};
+    use std::time::Instant;
+
+    const POSITIVE: &str = r#"[[{"label":"positive","score":0.8},{"label":"neutral","score":0.15},{"label":"negative","score":0.05}]]"#;
+
+    fn backend(url: &str, retry_policy: RetryPolicy) -> HuggingFaceBackend {
+        HuggingFaceBackend::new(
+            Client::new(),
+            url,
+            "model",
+            KeyPool::new(
+                vec!["key".to_string()],
+                KeyRotation::RoundRobin,
+                Duration::from_secs(60),
+            ),
+            LabelMapping::generic(),
+            retry_policy,
+            8,
+        )
+    }
+
+    /// Retries that would take far longer than any test if the server's
+    /// wait were ignored.
+    fn patient_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 2,
+            base_delay: Duration::from_secs(30),
+            max_delay: Duration::from_secs(60),
+            wait_for_model: false,
+        }
+    }
+
+    #[test]
+    fn a_loading_model_is_waited_for_as_estimated() {
+        let url = serve(vec![
+            (
+                503,
+                r#"{"error":"Model is loading","estimated_time":0.2}"#.into(),
+            ),
+            (200, POSITIVE.into()),
+        ]);
+        let started = Instant::now();
+        let report = backend(&url, patient_policy()).analyze("great").unwrap();
+        assert_eq!(report.positive_score, 0.8);
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(200), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+    }
+
+    #[test]
+    fn a_rate_limit_is_waited_for_as_the_server_asks() {
+        let url = serve_with_headers(vec![
+            (
+                429,
+                "Retry-After: 1\r\n",
+                r#"{"error":"Rate limit reached"}"#.into(),
+            ),
+            (200, "", POSITIVE.into()),
+        ]);
+        let started = Instant::now();
+        backend(&url, patient_policy()).analyze("great").unwrap();
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_secs(1), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+    }
+
+    #[test]
+    fn server_waits_are_capped() {
+        let url = serve(vec![
+            (
+                503,
+                r#"{"error":"Model is loading","estimated_time":600}"#.into(),
+            ),
+            (200, POSITIVE.into()),
+        ]);
+        let policy = RetryPolicy {
+            max_delay: Duration::from_millis(100),
+            ..patient_policy()
+        };
+        let started = Instant::now();
+        backend(&url, policy).analyze("great").unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn retries_stop_once_the_budget_is_spent() {
+        let url = serve(vec![
+            (500, String::new()),
+            (502, String::new()),
+            (504, r#"{"error":"Gateway timeout"}"#.into()),
+            (200, POSITIVE.into()),
+        ]);
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(10),
+            ..patient_policy()
+        };
+        let backend = backend(&url, policy);
+        assert!(matches!(
+            backend.analyze("great"),
+            Err(BackendError::Status { code: 504, .. })
+        ));
+        // The last response was left for the next request.
+        assert!(backend.analyze("great").is_ok());
+    }
+
+    #[test]
+    fn client_errors_are_not_retried() {
+        for code in [400, 401, 404] {
+            let url = serve(vec![
+                (code, r#"{"error":"No"}"#.into()),
+                (200, POSITIVE.into()),
+            ]);
+            match backend(&url, patient_policy()).analyze("great") {
+                Err(BackendError::Status { code: got, message }) => {
+                    assert_eq!((got, message.as_str()), (code, "No"))
+                }
+                other => panic!("{code}: {other:?}"),
+            }
+            // The 200 was not used up by a retry (a 401 also disabled the
+            // only key of the first backend).
+            assert!(
+                backend(&url, patient_policy()).analyze("great").is_ok(),
+                "{code}"
+            );
+        }
+    }
+
+    /// The scores of one input, as found in a response.
+    fn scores(positive: f64) -> String {
+        let rest = (1.0 - positive) / 2.0;
+        format!(
+            r#"[{{"label":"positive","score":{positive}}},{{"label":"neutral","score":{rest}}},{{"label":"negative","score":{rest}}}]"#
+        )
+    }
+
+    fn batch(positives: &[f64]) -> String {
+        let scores: Vec<_> = positives.iter().map(|positive| scores(*positive)).collect();
+        format!("[{}]", scores.join(","))
+    }
+
+    fn texts(texts: &[&str]) -> Vec<String> {
+        texts.iter().map(|text| text.to_string()).collect()
+    }
+
+    fn positives(results: &[Result<SentimentReport, BackendError>]) -> Vec<f64> {
+        results
+            .iter()
+            .map(|result| result.as_ref().unwrap().positive_score)
+            .collect()
+    }
+
+    #[test]
+    fn a_batch_is_sent_batch_size_texts_at_a_time() {
+        let url = serve(vec![(200, batch(&[0.1, 0.2])), (200, batch(&[0.3]))]);
+        let backend = HuggingFaceBackend {
+            batch_size: 2,
+            ..backend(&url, patient_policy())
+        };
+        let results = backend.analyze_batch(&texts(&["a", "b", "c"]));
+        assert_eq!(positives(&results), [0.1, 0.2, 0.3]);
+    }
+
+    #[test]
+    fn a_rejected_batch_is_retried_text_by_text() {
+        let url = serve(vec![
+            (400, r#"{"error":"Input is too long"}"#.into()),
+            (200, batch(&[0.1])),
+            (400, r#"{"error":"Input is too long"}"#.into()),
+            (200, batch(&[0.3])),
+        ]);
+        let results = backend(&url, patient_policy()).analyze_batch(&texts(&["a", "b", "c"]));
+        assert_eq!(results[0].as_ref().unwrap().positive_score, 0.1);
+        assert!(matches!(
+            results[1],
+            Err(BackendError::Status { code: 400, .. })
+        ));
+        assert_eq!(results[2].as_ref().unwrap().positive_score, 0.3);
+    }
+
+    #[test]
+    fn a_response_of_the_wrong_length_is_retried_text_by_text() {
+        let url = serve(vec![
+            (200, batch(&[0.9])),
+            (200, batch(&[0.1])),
+            (200, batch(&[0.2])),
+        ]);
+        let results = backend(&url, patient_policy()).analyze_batch(&texts(&["a", "b"]));
+        assert_eq!(positives(&results), [0.1, 0.2]);
+    }
+
+    #[test]
+    fn an_error_for_the_whole_batch_is_given_to_every_text() {
+        let url = serve(vec![
+            (500, r#"{"error":"Internal error"}"#.into()),
+            (200, batch(&[0.3])),
+        ]);
+        let backend = HuggingFaceBackend {
+            batch_size: 2,
+            ..backend(
+                &url,
+                RetryPolicy {
+                    max_retries: 0,
+                    ..patient_policy()
+                },
+            )
+        };
+        let results = backend.analyze_batch(&texts(&["a", "b", "c"]));
+        for result in &results[..2] {
+            assert!(matches!(
+                result,
+                Err(BackendError::Status { code: 500, .. })
+            ));
+        }
+        assert_eq!(results[2].as_ref().unwrap().positive_score, 0.3);
+    }
+
+    #[test]
+    fn a_renewed_key_is_reported_as_the_replacement_of_the_rejected_one() {
+        let url = serve(vec![
+            (401, r#"{"error":"Invalid credentials"}"#.into()),
+            (200, POSITIVE.into()),
+        ]);
+        let backend = backend(&url, patient_policy());
+        assert!(backend.analyze("great").unwrap_err().is_unauthorized());
+        assert!(backend.replace_api_key("hf_renewed_key"));
+        backend.analyze("great").unwrap();
+
+        let usage = backend.usage_report().unwrap();
+        let lines = usage.lines().collect::<Vec<_>>();
+        assert!(lines[1].ends_with("replaced by key 2"), "{usage}");
+        assert!(lines[2].ends_with("ok"), "{usage}");
+    }
+}
@@ -0,0 +1,366 @@
+use clap::ValueEnum;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// How the pool moves from one key to the next.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyRotation {
+    /// A different key for every request, spreading the load evenly.
+    #[default]
+    RoundRobin,
+    /// Stay on a key until it is rate limited (429), then move on.
+    OnRateLimit,
+}
+
+/// API keys read from a file, one per line (`#` starts a comment).
+///
+/// Kept apart from plain strings so that `Debug` never shows the keys.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ApiKeyList(pub Vec<String>);
+
+impl ApiKeyList {
+    /// Used as the clap value parser of `--key-pool`.
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let raw = std::fs::read_to_string(Path::new(path))
+            .map_err(|e| format!("could not read {path}: {e}"))?;
+        let keys = raw
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect::<Vec<_>>();
+        if keys.is_empty() {
+            return Err(format!("{path} holds no API key"));
+        }
+        Ok(ApiKeyList(keys))
+    }
+}
+
+impl fmt::Debug for ApiKeyList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ApiKeyList({} keys)", self.0.len())
+    }
+}
+
+/// What happened to a request sent with a pooled key.
+#[derive(Debug, Clone, Copy)]
+pub enum KeyOutcome {
+    Success,
+    /// Any other failure, counted but without consequence for the key.
+    Failure,
+    /// 429, the key rests for the given time (or the default cooldown).
+    RateLimited(Option<Duration>),
+    /// 401/402: revoked or out of credits. The key is not used again during
+    /// this session.
+    Disabled,
+}
+
+/// Several API keys shared by one backend, with usage tracked per key.
+pub struct KeyPool {
+    state: Mutex<PoolState>,
+    rotation: KeyRotation,
+    cooldown: Duration,
+}
+
+struct PoolState {
+    keys: Vec<PooledKey>,
+    /// Next key to try (round-robin) or key in use (on rate limit).
+    cursor: usize,
+}
+
+struct PooledKey {
+    secret: String,
+    requests: u64,
+    successes: u64,
+    failures: u64,
+    rate_limited: u64,
+    cooling_until: Option<Instant>,
+    disabled: bool,
+    /// Index of the key entered to replace this one once disabled.
+    replaced_by: Option<usize>,
+}
+
+impl PooledKey {
+    fn new(secret: String) -> Self {
+        PooledKey {
+            secret,
+            requests: 0,
+            successes: 0,
+            failures: 0,
+            rate_limited: 0,
+            cooling_until: None,
+            disabled: false,
+            replaced_by: None,
+        }
+    }
+
+    fn is_available(&self, now: Instant) -> bool {
+        !self.disabled && self.cooling_until.is_none_or(|until| until <= now)
+    }
+
+    /// `hf_…abcd`, enough to tell keys apart without revealing them.
+    fn masked(&self) -> String {
+        let chars = self.secret.chars().collect::<Vec<_>>();
+        if chars.len() <= 8 {
+            return "…".to_string();
+        }
+        let head = chars[..3].iter().collect::<String>();
+        let tail = chars[chars.len() - 4..].iter().collect::<String>();
+        format!("{head}…{tail}")
+    }
+}
+
+impl KeyPool {
+    pub fn new(keys: Vec<String>, rotation: KeyRotation, cooldown: Duration) -> Self {
+        KeyPool {
+            state: Mutex::new(PoolState {
+                keys: keys.into_iter().map(PooledKey::new).collect(),
+                cursor: 0,
+            }),
+            rotation,
+            cooldown,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, PoolState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Index and value of the key to send the next request with.
+    ///
+    /// When every key is resting, the one available soonest is returned: the
+    /// request will most likely be rate limited and retried after a wait.
+    /// Returns `None` only when every key was disabled.
+    pub fn pick(&self) -> Option<(usize, String)> {
+        let now = Instant::now();
+        let mut state = self.state();
+        let count = state.keys.len();
+        let start = state.cursor;
+        let available = (0..count)
+            .map(|offset| (start + offset) % count)
+            .find(|&index| state.keys[index].is_available(now));
+        let index = match available {
+            Some(index) => index,
+            None => state
+                .keys
+                .iter()
+                .enumerate()
+                .filter(|(_, key)| !key.disabled)
+                .min_by_key(|(_, key)| key.cooling_until)
+                .map(|(index, _)| index)?,
+        };
+        state.cursor = match self.rotation {
+            KeyRotation::RoundRobin => (index + 1) % count,
+            KeyRotation::OnRateLimit => index,
+        };
+        let key = &mut state.keys[index];
+        key.requests += 1;
+        Some((index, key.secret.clone()))
+    }
+
+    pub fn record(&self, index: usize, outcome: KeyOutcome) {
+        let mut state = self.state();
+        let Some(key) = state.keys.get_mut(index) else {
+            return;
+        };
+        match outcome {
+            KeyOutcome::Success => key.successes += 1,
+            KeyOutcome::Failure => key.failures += 1,
+            KeyOutcome::RateLimited(wait) => {
+                key.failures += 1;
+                key.rate_limited += 1;
+                key.cooling_until = Some(Instant::now() + wait.unwrap_or(self.cooldown));
+            }
+            KeyOutcome::Disabled => {
+                key.failures += 1;
+                key.disabled = true;
+            }
+        }
+    }
+
+    /// Whether a request could be sent right away with some key.
+    pub fn has_available(&self) -> bool {
+        let now = Instant::now();
+        self.state().keys.iter().any(|key| key.is_available(now))
+    }
+
+    /// Adds `api_key`, which goes first in line. The disabled keys stay in
+    /// the pool, unused, so that their usage is still reported as replaced
+    /// by the new one.
+    pub fn replace_disabled(&self, api_key: &str) {
+        let mut state = self.state();
+        // Appended rather than inserted: requests in flight record their
+        // outcome by index.
+        let index = state.keys.len();
+        for key in &mut state.keys {
+            if key.disabled && key.replaced_by.is_none() {
+                key.replaced_by = Some(index);
+            }
+        }
+        state.keys.push(PooledKey::new(api_key.to_string()));
+        state.cursor = index;
+    }
+
+    /// Per-key usage, one line per key. Nothing to report for a single key.
+    pub fn report(&self) -> Option<String> {
+        let now = Instant::now();
+        let state = self.state();
+        if state.keys.len() < 2 {
+            return None;
+        }
+        let mut lines = vec![format!(
+            "{:<4} {:<10} {:>9} {:>9} {:>9} {:>12}  status",
+            "key", "", "requests", "successes", "failures", "rate limited"
+        )];
+        for (index, key) in state.keys.iter().enumerate() {
+            let status = if let Some(replacement) = key.replaced_by {
+                format!("replaced by key {}", replacement + 1)
+            } else if key.disabled {
+                "disabled".to_string()
+            } else {
+                match key.cooling_until {
+                    Some(until) if until > now => {
+                        format!("cooling down ({}s left)", (until - now).as_secs())
+                    }
+                    _ => "ok".to_string(),
+                }
+            };
+            lines.push(format!(
+                "{:<4} {:<10} {:>9} {:>9} {:>9} {:>12}  {status}",
+                index + 1,
+                key.masked(),
+                key.requests,
+                key.successes,
+                key.failures,
+                key.rate_limited
+            ));
+        }
+        Some(lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(keys: &[&str]) -> KeyPool {
+        rotating(keys, KeyRotation::RoundRobin)
+    }
+
+    fn rotating(keys: &[&str], rotation: KeyRotation) -> KeyPool {
+        KeyPool::new(
+            keys.iter().map(|key| key.to_string()).collect(),
+            rotation,
+            Duration::from_secs(60),
+        )
+    }
+
+    /// Secrets of the next `count` keys picked, each request succeeding.
+    fn picks(pool: &KeyPool, count: usize) -> Vec<String> {
+        (0..count)
+            .map(|_| {
+                let (index, secret) = pool.pick().unwrap();
+                pool.record(index, KeyOutcome::Success);
+                secret
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_robin_uses_every_key_in_turn() {
+        let pool = pool(&["a", "b", "c"]);
+        assert_eq!(picks(&pool, 4), ["a", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn on_rate_limit_stays_on_a_key_until_it_is_rate_limited() {
+        let pool = rotating(&["a", "b", "c"], KeyRotation::OnRateLimit);
+        assert_eq!(picks(&pool, 3), ["a", "a", "a"]);
+        pool.record(0, KeyOutcome::RateLimited(None));
+        assert_eq!(picks(&pool, 2), ["b", "b"]);
+        pool.record(1, KeyOutcome::RateLimited(None));
+        assert_eq!(picks(&pool, 1), ["c"]);
+    }
+
+    #[test]
+    fn a_rate_limited_key_comes_back_after_its_cooldown() {
+        let pool = pool(&["a", "b"]);
+        let (index, _) = pool.pick().unwrap();
+        pool.record(
+            index,
+            KeyOutcome::RateLimited(Some(Duration::from_millis(100))),
+        );
+        assert_eq!(picks(&pool, 2), ["b", "b"]);
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(pool.has_available());
+        assert_eq!(picks(&pool, 2), ["a", "b"]);
+    }
+
+    #[test]
+    fn the_key_resting_the_least_is_picked_when_all_are_resting() {
+        let pool = pool(&["a", "b", "c"]);
+        pool.record(0, KeyOutcome::RateLimited(Some(Duration::from_secs(30))));
+        pool.record(1, KeyOutcome::RateLimited(Some(Duration::from_secs(10))));
+        pool.record(2, KeyOutcome::Disabled);
+        assert!(!pool.has_available());
+        assert_eq!(pool.pick(), Some((1, "b".to_string())));
+        assert_eq!(pool.pick(), Some((1, "b".to_string())));
+    }
+
+    #[test]
+    fn disabled_keys_are_still_reported_after_a_new_key_replaces_them() {
+        let pool = pool(&["hf_first_key_1111", "hf_second_key_2222"]);
+        let (first, _) = pool.pick().unwrap();
+        pool.record(first, KeyOutcome::Disabled);
+        let (second, _) = pool.pick().unwrap();
+        pool.record(second, KeyOutcome::Disabled);
+        assert_eq!(pool.pick(), None);
+
+        pool.replace_disabled("hf_third_key_3333");
+        for _ in 0..2 {
+            let (index, secret) = pool.pick().unwrap();
+            assert_eq!(secret, "hf_third_key_3333");
+            pool.record(index, KeyOutcome::Success);
+        }
+
+        let report = pool.report().unwrap();
+        let lines = report.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4, "{report}");
+        assert!(lines[1].contains("hf_…1111") && lines[1].ends_with("replaced by key 3"));
+        assert!(lines[2].contains("hf_…2222") && lines[2].ends_with("replaced by key 3"));
+        assert!(lines[3].contains("hf_…3333") && lines[3].ends_with("ok"));
+        assert_eq!(
+            lines[3].split_whitespace().nth(2),
+            Some("2"),
+            "requests of the new key: {report}"
+        );
+    }
+
+    #[test]
+    fn a_single_key_renewed_once_is_reported_as_replaced() {
+        let pool = pool(&["hf_first_key_1111"]);
+        let (first, _) = pool.pick().unwrap();
+        pool.record(first, KeyOutcome::Disabled);
+        pool.replace_disabled("hf_second_key_2222");
+        let (second, _) = pool.pick().unwrap();
+        pool.record(second, KeyOutcome::Success);
+
+        let report = pool.report().unwrap();
+        let lines = report.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3, "{report}");
+        assert!(lines[1].contains("hf_…1111") && lines[1].ends_with("replaced by key 2"));
+        assert!(lines[2].contains("hf_…2222") && lines[2].ends_with("ok"));
+
+        // A key disabled after the replacement is not said to be replaced.
+        pool.record(second, KeyOutcome::Disabled);
+        let report = pool.report().unwrap();
+        assert!(
+            report.lines().nth(2).unwrap().ends_with("disabled"),
+            "{report}"
+        );
+    }
+}
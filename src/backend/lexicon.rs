@@ -0,0 +1,527 @@
+use super::{BackendError, SentimentBackend};
+use crate::report::SentimentReport;
+
+/// Offline, rule based sentiment analysis in the spirit of VADER.
+///
+/// Every word found in the lexicon contributes its valence, adjusted for the
+/// words around it (negations, intensifiers, "but"), ALL-CAPS emphasis and
+/// trailing exclamation marks. No network access and no API key are needed.
+pub struct LexiconBackend;
+
+/// Valence added (or removed) by an intensifier preceding a word.
+const BOOSTER_INCREMENT: f64 = 0.293;
+/// Valence added to a lexicon word written in capitals within mixed-case text.
+const CAPS_INCREMENT: f64 = 0.733;
+/// Multiplier applied to a word preceded by a negation.
+const NEGATION_SCALAR: f64 = -0.74;
+/// Valence added per exclamation mark, up to `MAX_EXCLAMATIONS`.
+const EXCLAMATION_INCREMENT: f64 = 0.292;
+const MAX_EXCLAMATIONS: usize = 4;
+/// How many preceding tokens are looked at for negations and intensifiers.
+const LOOKBACK: usize = 3;
+
+impl SentimentBackend for LexiconBackend {
+    fn describe(&self) -> String {
+        "Built-in lexicon (offline)".to_string()
+    }
+
+    fn model(&self) -> String {
+        "lexicon".to_string()
+    }
+
+    fn labels(&self) -> Vec<String> {
+        ["negative", "neutral", "positive"]
+            .map(String::from)
+            .to_vec()
+    }
+
+    fn analyze(&self, text: &str) -> Result<SentimentReport, BackendError> {
+        Ok(score_text(text))
+    }
+}
+
+fn score_text(text: &str) -> SentimentReport {
+    let tokens = tokenize(text);
+    let is_shouting_everywhere = tokens
+        .iter()
+        .filter(|token| token.chars().any(char::is_alphabetic))
+        .all(|token| is_all_caps(token));
+
+    let mut valences: Vec<f64> = Vec::with_capacity(tokens.len());
+    for (index, token) in tokens.iter().enumerate() {
+        let Some(mut valence) = valence_of(token) else {
+            valences.push(0.0);
+            continue;
+        };
+
+        if !is_shouting_everywhere && is_all_caps(token) {
+            valence += CAPS_INCREMENT * valence.signum();
+        }
+
+        let preceding = &tokens[index.saturating_sub(LOOKBACK)..index];
+        for (distance, previous) in preceding.iter().rev().enumerate() {
+            let previous = previous.to_lowercase();
+            if let Some(boost) = booster(&previous) {
+                // The further away the intensifier, the weaker its effect.
+                let decay = [1.0, 0.95, 0.9][distance];
+                valence += boost * decay * valence.signum();
+            }
+        }
+        if preceding
+            .iter()
+            .any(|previous| is_negation(&previous.to_lowercase()))
+        {
+            valence *= NEGATION_SCALAR;
+        }
+
+        valences.push(valence);
+    }
+
+    // "The food was great but the service was awful": what follows "but" matters more.
+    if let Some(but_index) = tokens
+        .iter()
+        .position(|token| token.eq_ignore_ascii_case("but"))
+    {
+        for (index, valence) in valences.iter_mut().enumerate() {
+            if index < but_index {
+                *valence *= 0.5;
+            } else if index > but_index {
+                *valence *= 1.5;
+            }
+        }
+    }
+
+    let exclamations = text
+        .chars()
+        .filter(|&c| c == '!')
+        .count()
+        .min(MAX_EXCLAMATIONS);
+    let emphasis = exclamations as f64 * EXCLAMATION_INCREMENT;
+
+    let mut positive_sum = 0.0;
+    let mut negative_sum = 0.0;
+    let mut neutral_count = 0.0;
+    for &valence in &valences {
+        if valence > 0.0 {
+            positive_sum += valence + 1.0;
+        } else if valence < 0.0 {
+            negative_sum += valence - 1.0;
+        } else {
+            neutral_count += 1.0;
+        }
+    }
+    // Exclamation marks amplify whichever side is already winning.
+    if positive_sum > negative_sum.abs() {
+        positive_sum += emphasis;
+    } else if positive_sum < negative_sum.abs() {
+        negative_sum -= emphasis;
+    }
+
+    let total = positive_sum + negative_sum.abs() + neutral_count;
+    if total == 0.0 {
+        return SentimentReport {
+            neutral_score: 1.0,
+            positive_score: 0.0,
+            negative_score: 0.0,
+            stars: None,
+            chunks: Vec::new(),
+        };
+    }
+    SentimentReport {
+        neutral_score: neutral_count / total,
+        positive_score: positive_sum / total,
+        negative_score: negative_sum.abs() / total,
+        stars: None,
+        chunks: Vec::new(),
+    }
+}
+
+/// Splits on whitespace, keeps emoticons whole, strips surrounding punctuation
+/// and pulls emoji glued to words out into their own tokens.
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for raw in text.split_whitespace() {
+        if EMOTICONS.iter().any(|(emoticon, _)| *emoticon == raw) {
+            tokens.push(raw.to_string());
+            continue;
+        }
+        let mut word = String::new();
+        for c in raw.chars() {
+            if EMOJI.iter().any(|(emoji, _)| *emoji == c) {
+                if !word.is_empty() {
+                    tokens.push(std::mem::take(&mut word));
+                }
+                tokens.push(c.to_string());
+            } else {
+                word.push(c);
+            }
+        }
+        let word = word.trim_matches(|c: char| !c.is_alphanumeric() && c != '\'');
+        if !word.is_empty() {
+            tokens.push(word.to_string());
+        }
+    }
+    tokens
+}
+
+fn is_all_caps(token: &str) -> bool {
+    token.chars().any(char::is_alphabetic) && !token.chars().any(char::is_lowercase)
+}
+
+fn is_negation(word: &str) -> bool {
+    NEGATIONS.contains(&word) || word.ends_with("n't")
+}
+
+fn booster(word: &str) -> Option<f64> {
+    if BOOSTERS.contains(&word) {
+        Some(BOOSTER_INCREMENT)
+    } else if DAMPENERS.contains(&word) {
+        Some(-BOOSTER_INCREMENT)
+    } else {
+        None
+    }
+}
+
+fn valence_of(token: &str) -> Option<f64> {
+    let lowered = token.to_lowercase();
+    if let Some((_, valence)) = WORDS.iter().find(|(word, _)| *word == lowered) {
+        return Some(*valence);
+    }
+    if let Some((_, valence)) = EMOTICONS.iter().find(|(emoticon, _)| *emoticon == token) {
+        return Some(*valence);
+    }
+    let mut chars = token.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => EMOJI
+            .iter()
+            .find(|(emoji, _)| *emoji == c)
+            .map(|(_, valence)| *valence),
+        _ => None,
+    }
+}
+
+static NEGATIONS: &[&str] = &[
+    "not", "no", "never", "nothing", "nobody", "none", "neither", "nor", "nowhere", "without",
+    "cannot", "dont", "doesnt", "didnt", "isnt", "wasnt", "arent", "werent", "wont", "cant",
+    "couldnt", "shouldnt", "wouldnt",
+];
+
+static BOOSTERS: &[&str] = &[
+    "absolutely",
+    "amazingly",
+    "completely",
+    "deeply",
+    "enormously",
+    "entirely",
+    "especially",
+    "exceptionally",
+    "extremely",
+    "fully",
+    "greatly",
+    "highly",
+    "hugely",
+    "incredibly",
+    "intensely",
+    "majorly",
+    "more",
+    "most",
+    "particularly",
+    "purely",
+    "quite",
+    "really",
+    "remarkably",
+    "so",
+    "substantially",
+    "thoroughly",
+    "totally",
+    "tremendously",
+    "truly",
+    "utterly",
+    "very",
+];
+
+static DAMPENERS: &[&str] = &[
+    "almost",
+    "barely",
+    "hardly",
+    "kinda",
+    "kindof",
+    "less",
+    "little",
+    "marginally",
+    "occasionally",
+    "partly",
+    "scarcely",
+    "slightly",
+    "somewhat",
+    "sorta",
+];
+
+static WORDS: &[(&str, f64)] = &[
+    ("abandon", -1.9),
+    ("abuse", -3.2),
+    ("accept", 1.6),
+    ("admire", 2.1),
+    ("adore", 2.6),
+    ("afraid", -2.2),
+    ("agree", 1.5),
+    ("amazing", 2.8),
+    ("angry", -2.3),
+    ("annoying", -1.7),
+    ("anxious", -1.0),
+    ("awesome", 3.1),
+    ("awful", -2.0),
+    ("bad", -2.5),
+    ("bankrupt", -2.6),
+    ("beautiful", 2.9),
+    ("best", 3.2),
+    ("better", 1.9),
+    ("bored", -1.1),
+    ("boring", -1.3),
+    ("brilliant", 2.8),
+    ("broken", -1.9),
+    ("bullish", 1.8),
+    ("bearish", -1.8),
+    ("calm", 1.3),
+    ("careless", -1.5),
+    ("celebrate", 2.7),
+    ("cheerful", 2.5),
+    ("collapse", -2.2),
+    ("comfortable", 1.5),
+    ("confident", 2.2),
+    ("confused", -1.3),
+    ("crash", -1.7),
+    ("crisis", -3.1),
+    ("cry", -2.1),
+    ("damage", -2.2),
+    ("danger", -2.4),
+    ("dead", -3.3),
+    ("decline", -1.1),
+    ("delight", 2.9),
+    ("depressed", -2.3),
+    ("disappointed", -1.9),
+    ("disaster", -3.1),
+    ("disgusting", -2.4),
+    ("dislike", -1.6),
+    ("dream", 1.0),
+    ("easy", 1.9),
+    ("enjoy", 2.2),
+    ("evil", -3.4),
+    ("excellent", 2.7),
+    ("excited", 2.0),
+    ("fail", -2.5),
+    ("failure", -2.3),
+    ("fantastic", 2.6),
+    ("fear", -2.2),
+    ("fine", 0.8),
+    ("fraud", -2.8),
+    ("free", 2.3),
+    ("fun", 2.3),
+    ("gain", 2.4),
+    ("glad", 2.0),
+    ("good", 1.9),
+    ("great", 3.1),
+    ("grief", -2.2),
+    ("growth", 1.6),
+    ("happy", 2.7),
+    ("hate", -2.7),
+    ("help", 1.7),
+    ("hope", 1.9),
+    ("horrible", -2.5),
+    ("hurt", -2.4),
+    ("ideal", 2.4),
+    ("ill", -1.8),
+    ("impressive", 2.3),
+    ("improve", 1.9),
+    ("interesting", 1.7),
+    ("joy", 2.8),
+    ("kill", -3.7),
+    ("kind", 2.4),
+    ("lawsuit", -1.6),
+    ("lose", -1.3),
+    ("loss", -1.3),
+    ("lost", -1.3),
+    ("love", 3.2),
+    ("lovely", 2.8),
+    ("lucky", 1.8),
+    ("mad", -2.2),
+    ("mess", -1.5),
+    ("miss", -0.6),
+    ("nice", 1.8),
+    ("pain", -2.3),
+    ("panic", -2.3),
+    ("perfect", 2.7),
+    ("pleasant", 2.3),
+    ("poor", -2.1),
+    ("positive", 2.3),
+    ("negative", -2.7),
+    ("problem", -1.7),
+    ("profit", 1.9),
+    ("proud", 2.1),
+    ("rally", 1.5),
+    ("recession", -2.2),
+    ("recover", 1.5),
+    ("regret", -1.8),
+    ("rich", 2.6),
+    ("risk", -1.1),
+    ("ruin", -2.8),
+    ("sad", -2.1),
+    ("safe", 1.9),
+    ("scam", -2.8),
+    ("scared", -1.9),
+    ("soar", 2.0),
+    ("sorry", -0.3),
+    ("strong", 2.3),
+    ("stupid", -2.4),
+    ("success", 2.7),
+    ("successful", 2.8),
+    ("suffer", -2.1),
+    ("superb", 3.1),
+    ("terrible", -2.1),
+    ("thank", 1.5),
+    ("thanks", 1.9),
+    ("threat", -2.4),
+    ("tragedy", -3.4),
+    ("trouble", -1.7),
+    ("trust", 2.3),
+    ("ugly", -2.3),
+    ("unhappy", -1.8),
+    ("upset", -1.6),
+    ("useful", 1.9),
+    ("useless", -1.8),
+    ("victory", 2.8),
+    ("warning", -1.4),
+    ("weak", -1.9),
+    ("win", 2.8),
+    ("wonderful", 2.7),
+    ("worried", -1.2),
+    ("worse", -2.1),
+    ("worst", -3.1),
+    ("wow", 2.8),
+    ("wrong", -2.1),
+    ("yay", 2.4),
+];
+
+static EMOTICONS: &[(&str, f64)] = &[
+    (":)", 2.0),
+    (":-)", 2.2),
+    (":D", 2.3),
+    (";)", 1.7),
+    ("<3", 1.9),
+    (":(", -1.9),
+    (":-(", -1.9),
+    (":'(", -2.2),
+    (":/", -1.4),
+];
+
+static EMOJI: &[(char, f64)] = &[
+    ('😀', 2.2),
+    ('😁', 2.2),
+    ('😂', 1.5),
+    ('😊', 2.4),
+    ('😍', 2.9),
+    ('🥰', 2.9),
+    ('👍', 1.8),
+    ('🎉', 2.3),
+    ('❤', 2.9),
+    ('🚀', 1.5),
+    ('🙂', 1.6),
+    ('😕', -1.2),
+    ('🙁', -1.6),
+    ('😢', -2.1),
+    ('😭', -2.2),
+    ('😡', -2.7),
+    ('😠', -2.3),
+    ('👎', -1.8),
+    ('💩', -1.9),
+    ('📉', -1.4),
+    ('📈', 1.4),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::Sentiment;
+
+    /// The scores are shares of the text, texts compared with each other
+    /// have the same number of words.
+    fn compound(text: &str) -> f64 {
+        score_text(text).compound()
+    }
+
+    #[test]
+    fn lexicon_words_set_the_sentiment() {
+        assert_eq!(score_text("great").dominant(), Sentiment::Positive);
+        assert_eq!(score_text("disaster").dominant(), Sentiment::Negative);
+        assert!(compound("what a great day") > 0.0);
+        assert!(compound("this is a disaster") < 0.0);
+        let report = score_text("the meeting is at noon");
+        assert_eq!(report.dominant(), Sentiment::Neutral);
+        assert_eq!(report.compound(), 0.0);
+    }
+
+    #[test]
+    fn negations_flip_the_following_words() {
+        assert!(compound("this is not good") < 0.0);
+        assert!(compound("it isn't bad") > 0.0);
+        assert!(compound("never ever happy") < 0.0);
+        // Out of the lookback window.
+        assert!(compound("not that it matters good") > 0.0);
+    }
+
+    #[test]
+    fn dampeners_weaken_without_negating() {
+        assert!(compound("barely good") > 0.0);
+        assert!(compound("hardly good") > 0.0);
+        assert!(compound("barely good") < compound("fairly good"));
+    }
+
+    #[test]
+    fn boosters_strengthen_and_fade_with_distance() {
+        assert!(compound("very good") > compound("fairly good"));
+        assert!(compound("extremely bad") < compound("fairly bad"));
+        assert!(compound("very the good") < compound("the very good"));
+        assert_eq!(
+            compound("very a the an good"),
+            compound("fairly a the an good")
+        );
+    }
+
+    #[test]
+    fn capitals_emphasize_only_within_mixed_case_text() {
+        assert!(compound("the food is GOOD") > compound("the food is good"));
+        assert_eq!(
+            score_text("THE FOOD IS GOOD"),
+            score_text("the food is good")
+        );
+    }
+
+    #[test]
+    fn what_follows_but_weighs_more() {
+        assert!(compound("the food was great but the service was awful") < 0.0);
+        assert!(compound("the food was awful but the service was great") > 0.0);
+    }
+
+    #[test]
+    fn exclamation_marks_amplify_the_winning_side() {
+        assert!(compound("the food is good!!") > compound("the food is good"));
+        assert_eq!(
+            compound("the food is good!!!!!!!!"),
+            compound("the food is good!!!!")
+        );
+        assert!(compound("the food is bad!") < compound("the food is bad"));
+    }
+
+    #[test]
+    fn emoji_and_emoticons_count() {
+        assert_eq!(tokenize("love😍it :)"), ["love", "😍", "it", ":)"]);
+        assert_eq!(score_text(":(").dominant(), Sentiment::Negative);
+    }
+
+    #[test]
+    fn empty_text_is_neutral() {
+        let report = score_text("");
+        assert_eq!(report.neutral_score, 1.0);
+        assert_eq!(report.compound(), 0.0);
+    }
+}
@@ -0,0 +1,311 @@
+use super::{BackendError, SentimentBackend};
+use crate::labels::LabelMapping;
+use crate::report::{LabelScore, SentimentReport};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokenizers::{Tokenizer, TruncationParams};
+use tract_onnx::prelude::*;
+
+/// Longest sequence (in tokens) RoBERTa-style models accept.
+const MAX_SEQUENCE_LENGTH: usize = 512;
+
+/// CPU inference of a sentiment model exported to ONNX, read from a directory
+/// holding `model.onnx`, `tokenizer.json` and the `config.json` of the model.
+///
+/// This is what `optimum-cli export onnx --model cardiffnlp/twitter-roberta-base-sentiment-latest`
+/// produces.
+pub struct LocalModelBackend {
+    model_dir: PathBuf,
+    model: Arc<TypedRunnableModel>,
+    tokenizer: Tokenizer,
+    /// Label of every logit, in output order (`id2label` of `config.json`).
+    labels: Vec<String>,
+    label_mapping: LabelMapping,
+    input_count: usize,
+}
+
+impl LocalModelBackend {
+    /// Loads the model of `model_dir`.
+    ///
+    /// Without a `label_mapping`, the one of a built-in model is used when the
+    /// export names one (`_name_or_path` of `config.json`) or `model_id` is
+    /// one, and its labels are those of the export. The generic mapping is
+    /// the last resort.
+    pub fn load(
+        model_dir: &Path,
+        model_id: &str,
+        label_mapping: Option<LabelMapping>,
+    ) -> Result<Self, String> {
+        let model = tract_onnx::onnx()
+            .model_for_path(model_dir.join("model.onnx"))
+            .and_then(|model| model.into_optimized())
+            .and_then(|model| model.into_runnable())
+            .map_err(|e| format!("Could not load model.onnx: {e}"))?;
+        let input_count = model
+            .model()
+            .input_outlets()
+            .map_err(|e| format!("Could not inspect model.onnx: {e}"))?
+            .len();
+
+        let mut tokenizer = Tokenizer::from_file(model_dir.join("tokenizer.json"))
+            .map_err(|e| format!("Could not load tokenizer.json: {e}"))?;
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: MAX_SEQUENCE_LENGTH,
+                ..Default::default()
+            }))
+            .map_err(|e| format!("Could not configure the tokenizer: {e}"))?;
+
+        let config = read_config(&model_dir.join("config.json"))?;
+        let labels = read_labels(&config)?;
+        let label_mapping =
+            label_mapping.unwrap_or_else(|| builtin_mapping(&config, &labels, model_id));
+        Ok(LocalModelBackend {
+            model_dir: model_dir.to_path_buf(),
+            model,
+            tokenizer,
+            labels,
+            label_mapping,
+            input_count,
+        })
+    }
+
+    fn logits(&self, text: &str) -> Result<Vec<f32>, BackendError> {
+        let encoding = self
+            .tokenizer
+            .encode(text, true)
+            .map_err(|e| BackendError::Inference(e.to_string()))?;
+        let as_tensor = |values: &[u32]| -> TractResult<TValue> {
+            let values = values.iter().map(|&value| value as i64).collect::<Vec<_>>();
+            Ok(
+                tract_ndarray::Array2::from_shape_vec((1, values.len()), values)?
+                    .into_tensor()
+                    .into(),
+            )
+        };
+
+        let inference = || -> TractResult<Vec<f32>> {
+            let mut inputs = tvec!(
+                as_tensor(encoding.get_ids())?,
+                as_tensor(encoding.get_attention_mask())?
+            );
+            // BERT-style exports also expect token type ids.
+            if self.input_count > 2 {
+                inputs.push(as_tensor(encoding.get_type_ids())?);
+            }
+            let outputs = self.model.run(inputs)?;
+            Ok(outputs[0]
+                .to_plain_array_view::<f32>()?
+                .iter()
+                .copied()
+                .collect())
+        };
+        inference().map_err(|e| BackendError::Inference(e.to_string()))
+    }
+}
+
+impl SentimentBackend for LocalModelBackend {
+    fn describe(&self) -> String {
+        format!(
+            "Local ONNX model ({})\nLabel mapping: {}",
+            self.model_dir.display(),
+            self.label_mapping.describe()
+        )
+    }
+
+    fn model(&self) -> String {
+        self.model_dir.display().to_string()
+    }
+
+    fn labels(&self) -> Vec<String> {
+        self.labels.clone()
+    }
+
+    fn analyze(&self, text: &str) -> Result<SentimentReport, BackendError> {
+        let logits = self.logits(text)?;
+        to_report(&self.labels, &logits, &self.label_mapping)
+    }
+}
+
+/// Report of the `logits` of a model whose outputs are `labels`.
+fn to_report(
+    labels: &[String],
+    logits: &[f32],
+    label_mapping: &LabelMapping,
+) -> Result<SentimentReport, BackendError> {
+    if logits.len() != labels.len() {
+        return Err(BackendError::Inference(format!(
+            "model produced {} logits for {} labels",
+            logits.len(),
+            labels.len()
+        )));
+    }
+    let label_scores = labels
+        .iter()
+        .zip(softmax(logits))
+        .map(|(label, score)| LabelScore {
+            label: label.clone(),
+            score,
+        })
+        .collect::<Vec<_>>();
+    Ok(label_mapping.apply(&label_scores)?)
+}
+
+/// Probabilities of `logits`, shifted by the max logit for numerical stability.
+fn softmax(logits: &[f32]) -> Vec<f64> {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let exponentials = logits
+        .iter()
+        .map(|logit| (logit - max).exp())
+        .collect::<Vec<_>>();
+    let total: f32 = exponentials.iter().sum();
+    exponentials
+        .into_iter()
+        .map(|exponential| (exponential / total) as f64)
+        .collect()
+}
+
+fn read_config(config_path: &Path) -> Result<serde_json::Value, String> {
+    fs::read_to_string(config_path)
+        .map_err(|e| e.to_string())
+        .and_then(|raw| serde_json::from_str(&raw).map_err(|e| e.to_string()))
+        .map_err(|e| format!("Could not read config.json: {e}"))
+}
+
+/// Reads `id2label` out of the model's `config.json`.
+fn read_labels(config: &serde_json::Value) -> Result<Vec<String>, String> {
+    let id2label = config
+        .get("id2label")
+        .and_then(|id2label| id2label.as_object())
+        .ok_or("config.json has no id2label mapping")?;
+
+    let mut labels = id2label
+        .iter()
+        .map(|(id, label)| {
+            let id = id
+                .parse::<usize>()
+                .map_err(|_| format!("invalid label id '{id}' in config.json"))?;
+            let label = label
+                .as_str()
+                .ok_or(format!("label {id} in config.json is not a string"))?;
+            Ok((id, label.to_string()))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    labels.sort_by_key(|(id, _)| *id);
+    Ok(labels.into_iter().map(|(_, label)| label).collect())
+}
+
+/// Mapping of the model the export was made from, or of `model_id`, as long
+/// as it knows every one of `labels`. Otherwise the generic mapping.
+fn builtin_mapping(config: &serde_json::Value, labels: &[String], model_id: &str) -> LabelMapping {
+    let exported_from = config.get("_name_or_path").and_then(|name| name.as_str());
+    exported_from
+        .into_iter()
+        .chain([model_id])
+        .filter_map(LabelMapping::builtin)
+        .find(|mapping| mapping.covers(labels))
+        .unwrap_or_else(LabelMapping::generic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::ReportError;
+    use serde_json::json;
+
+    /// An export of cardiffnlp/twitter-roberta-base-sentiment (`LABEL_0/1/2`)
+    /// whose graph answers the logits -1, 0 and 2 whatever the text.
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/local-model");
+
+    fn labels(labels: &[&str]) -> Vec<String> {
+        labels.iter().map(|label| label.to_string()).collect()
+    }
+
+    #[test]
+    fn labels_are_read_in_id_order() {
+        let config =
+            json!({"id2label": {"2": "positive", "0": "negative", "10": "x", "1": "neutral"}});
+        assert_eq!(
+            read_labels(&config).unwrap(),
+            ["negative", "neutral", "positive", "x"]
+        );
+        assert!(read_labels(&json!({})).is_err());
+        assert!(read_labels(&json!({"id2label": {"first": "negative"}})).is_err());
+        assert!(read_labels(&json!({"id2label": {"0": 1}})).is_err());
+    }
+
+    #[test]
+    fn softmax_sums_to_one_and_keeps_the_order() {
+        let probabilities = softmax(&[1.0, 2.0, 3.0]);
+        assert!((probabilities.iter().sum::<f64>() - 1.0).abs() < 1e-6);
+        assert!(probabilities[0] < probabilities[1] && probabilities[1] < probabilities[2]);
+        // Large logits would overflow `exp` without the shift.
+        let probabilities = softmax(&[1000.0, 1000.0]);
+        assert!((probabilities[0] - 0.5).abs() < 1e-6, "{probabilities:?}");
+    }
+
+    #[test]
+    fn logits_are_mapped_to_the_report_through_the_labels() {
+        let mapping = LabelMapping::for_model("cardiffnlp/twitter-roberta-base-sentiment");
+        let report = to_report(
+            &labels(&["LABEL_0", "LABEL_1", "LABEL_2"]),
+            &[0.0, 0.0, 2.0_f32.ln()],
+            &mapping,
+        )
+        .unwrap();
+        assert!((report.negative_score - 0.25).abs() < 1e-6);
+        assert!((report.neutral_score - 0.25).abs() < 1e-6);
+        assert!((report.positive_score - 0.5).abs() < 1e-6);
+
+        let unknown = to_report(&labels(&["LABEL_0"]), &[1.0], &LabelMapping::generic());
+        assert!(matches!(
+            unknown,
+            Err(BackendError::Report(ReportError::UnexpectedLabel(_)))
+        ));
+        let mismatch = to_report(&labels(&["negative"]), &[1.0, 2.0], &mapping);
+        assert!(matches!(mismatch, Err(BackendError::Inference(_))));
+    }
+
+    #[test]
+    fn the_builtin_mapping_is_found_from_the_export_or_the_model() {
+        let label_ids = labels(&["LABEL_0", "LABEL_1", "LABEL_2"]);
+        let exported = json!({"_name_or_path": "cardiffnlp/twitter-roberta-base-sentiment"});
+        let mapping = builtin_mapping(&exported, &label_ids, "someone/else");
+        assert!(mapping.covers(&label_ids));
+
+        let anonymous = json!({});
+        let mapping = builtin_mapping(
+            &anonymous,
+            &label_ids,
+            "cardiffnlp/twitter-roberta-base-sentiment",
+        );
+        assert!(mapping.covers(&label_ids));
+
+        // The default model does not know LABEL_n, the generic mapping neither.
+        let mapping = builtin_mapping(
+            &anonymous,
+            &label_ids,
+            "cardiffnlp/twitter-roberta-base-sentiment-latest",
+        );
+        assert!(!mapping.covers(&label_ids));
+    }
+
+    #[test]
+    fn a_builtin_model_is_analyzed_without_labels_being_given() {
+        // Neither --labels nor --model: the export names its model.
+        let cli = <crate::cli::Cli as clap::Parser>::try_parse_from([
+            "sentiment_analyzer",
+            "--model-dir",
+            FIXTURE,
+        ])
+        .unwrap();
+        let settings = cli.backend_settings(String::new());
+        let backend = crate::backend::BackendKind::Local
+            .build(&reqwest::blocking::Client::new(), &settings)
+            .unwrap();
+        assert_eq!(backend.labels(), ["LABEL_0", "LABEL_1", "LABEL_2"]);
+        let report = backend.analyze("good day").unwrap();
+        assert!(report.positive_score > report.neutral_score);
+        assert!(report.neutral_score > report.negative_score);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::labels::LabelMapping;
+use crate::report::{ReportError, SentimentReport};
+use clap::ValueEnum;
+use reqwest::blocking::Client;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+use strum_macros::Display;
+
+mod huggingface;
+mod keypool;
+mod lexicon;
+#[cfg(feature = "local-model")]
+mod local;
+mod retry;
+
+pub use huggingface::{HuggingFaceBackend, HF_MODELS_URL};
+pub use keypool::{ApiKeyList, KeyPool, KeyRotation};
+pub use lexicon::LexiconBackend;
+#[cfg(feature = "local-model")]
+pub use local::LocalModelBackend;
+pub use retry::RetryPolicy;
+
+/// Everything the backends may need to be built, most only use part of it.
+#[derive(Debug, Clone)]
+pub struct BackendSettings {
+    /// Base URL of the Inference API (remote backend).
+    pub endpoint: String,
+    /// HuggingFace model id, also used to find the labels of a local model.
+    pub model_id: String,
+    pub api_key: String,
+    /// Keys used in turn instead of `api_key` (remote backend).
+    pub key_pool: Option<ApiKeyList>,
+    pub key_rotation: KeyRotation,
+    /// How long a rate limited key rests when the API does not say.
+    pub key_cooldown: Duration,
+    /// Directory of an exported model (local backend).
+    #[cfg_attr(not(feature = "local-model"), allow(dead_code))]
+    pub model_dir: Option<PathBuf>,
+    /// Overrides the label mapping derived from the model.
+    pub label_mapping: Option<LabelMapping>,
+    /// Retry behaviour of the remote backend.
+    pub retry_policy: RetryPolicy,
+    /// Texts sent per request by `analyze_batch` (remote backend).
+    pub batch_size: usize,
+}
+
+/// The backends that can be picked from the menu or with `--backend`.
+#[derive(ValueEnum, Display, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    /// Remote inference through the HuggingFace Inference API (needs an API key).
+    #[value(name = "huggingface")]
+    #[strum(serialize = "HuggingFace Inference API")]
+    HuggingFace,
+    /// Built-in lexicon, works offline and without an API key.
+    #[strum(serialize = "Offline lexicon")]
+    Lexicon,
+    /// ONNX export of a model on disk, run on the CPU (see `--model-dir`).
+    #[cfg(feature = "local-model")]
+    #[strum(serialize = "Local model")]
+    Local,
+}
+
+impl BackendKind {
+    pub fn needs_api_key(&self) -> bool {
+        matches!(self, BackendKind::HuggingFace)
+    }
+
+    /// Whether the model only reads the first few hundred tokens of a text,
+    /// so that long texts have to be chunked.
+    pub fn has_token_limit(&self) -> bool {
+        !matches!(self, BackendKind::Lexicon)
+    }
+
+    /// Whether results are worth keeping in the result cache. The lexicon
+    /// is quicker than a cache lookup, and its rules change with the program
+    /// while its description (the cache namespace) does not.
+    pub fn is_worth_caching(&self) -> bool {
+        !matches!(self, BackendKind::Lexicon)
+    }
+
+    pub fn needs_model_dir(&self) -> bool {
+        #[cfg(feature = "local-model")]
+        if matches!(self, BackendKind::Local) {
+            return true;
+        }
+        false
+    }
+
+    /// Every backend compiled into this binary, in menu order.
+    pub fn available() -> Vec<BackendKind> {
+        vec![
+            BackendKind::HuggingFace,
+            BackendKind::Lexicon,
+            #[cfg(feature = "local-model")]
+            BackendKind::Local,
+        ]
+    }
+
+    pub fn build(
+        self,
+        client: &Client,
+        settings: &BackendSettings,
+    ) -> Result<Box<dyn SentimentBackend>, String> {
+        match self {
+            BackendKind::HuggingFace => {
+                let label_mapping = settings
+                    .label_mapping
+                    .clone()
+                    .unwrap_or_else(|| LabelMapping::for_model(&settings.model_id));
+                Ok(Box::new(HuggingFaceBackend::new(
+                    client.clone(),
+                    &settings.endpoint,
+                    &settings.model_id,
+                    KeyPool::new(
+                        match &settings.key_pool {
+                            Some(ApiKeyList(keys)) => keys.clone(),
+                            None => vec![settings.api_key.clone()],
+                        },
+                        settings.key_rotation,
+                        settings.key_cooldown,
+                    ),
+                    label_mapping,
+                    settings.retry_policy,
+                    settings.batch_size,
+                )))
+            }
+            BackendKind::Lexicon => Ok(Box::new(LexiconBackend)),
+            #[cfg(feature = "local-model")]
+            BackendKind::Local => {
+                let model_dir = settings
+                    .model_dir
+                    .as_deref()
+                    .ok_or("The local backend needs --model-dir")?;
+                Ok(Box::new(LocalModelBackend::load(
+                    model_dir,
+                    &settings.model_id,
+                    settings.label_mapping.clone(),
+                )?))
+            }
+        }
+    }
+}
+
+/// Anything able to turn text into a `SentimentReport`.
+///
+/// The protocol loops only ever talk to this trait, so adding a new source of
+/// inference (offline, mock, another provider) does not require touching them.
+///
+/// Backends are shared with the worker threads of the `pipeline`, hence `Send + Sync`.
+pub trait SentimentBackend: Send + Sync {
+    /// Short human readable description, e.g. the model the backend runs.
+    fn describe(&self) -> String;
+
+    /// Identifier of the model, recorded with every machine readable result.
+    fn model(&self) -> String;
+
+    /// Labels the underlying model emits, in the order it emits them.
+    fn labels(&self) -> Vec<String>;
+
+    fn analyze(&self, text: &str) -> Result<SentimentReport, BackendError>;
+
+    /// Analyzes several texts, results are in the same order as `texts`.
+    ///
+    /// The default implementation analyzes the texts one at a time.
+    fn analyze_batch(&self, texts: &[String]) -> Vec<Result<SentimentReport, BackendError>> {
+        texts.iter().map(|text| self.analyze(text)).collect()
+    }
+
+    /// Switches to another API key for the following requests.
+    ///
+    /// Returns `false` for backends that do not use one.
+    fn replace_api_key(&self, _api_key: &str) -> bool {
+        false
+    }
+
+    /// Per-key statistics, for backends using several API keys.
+    fn usage_report(&self) -> Option<String> {
+        None
+    }
+
+    /// Share of the texts served from the result cache so far, for cached backends.
+    fn cache_hit_rate(&self) -> Option<f64> {
+        None
+    }
+
+    /// Saves what the backend accumulated during the run (e.g. cache
+    /// statistics), once the run is over.
+    fn finish(&self) {}
+}
+
+#[derive(Debug, Clone)]
+pub enum BackendError {
+    /// The request never made it to the backend (DNS, TLS, timeout...).
+    Transport(String),
+    /// The backend answered with a non-success HTTP status.
+    Status { code: u16, message: String },
+    /// The backend answered, but not with something we could understand.
+    Report(ReportError),
+    /// A local model failed to run.
+    #[cfg(feature = "local-model")]
+    Inference(String),
+}
+
+impl BackendError {
+    /// The API key was not accepted, retrying with the same one is pointless.
+    pub fn is_unauthorized(&self) -> bool {
+        matches!(self, BackendError::Status { code: 401, .. })
+    }
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendError::Transport(err) => write!(f, "Sentiment analysis request failed: {err}"),
+            BackendError::Status { code, message } if message.is_empty() => {
+                write!(f, "Sentiment analysis failed with status {code}")
+            }
+            BackendError::Status { code, message } => {
+                write!(f, "Sentiment analysis failed with status {code}: {message}")
+            }
+            BackendError::Report(err) => write!(f, "{err}"),
+            #[cfg(feature = "local-model")]
+            BackendError::Inference(err) => write!(f, "Local inference failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+impl From<ReportError> for BackendError {
+    fn from(err: ReportError) -> Self {
+        BackendError::Report(err)
+    }
+}
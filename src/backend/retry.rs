@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+/// How hard a backend tries before giving up on a request.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Retries on top of the first attempt, `0` disables retrying.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled on every following one.
+    pub base_delay: Duration,
+    /// Upper bound of any single wait, including server provided ones.
+    pub max_delay: Duration,
+    /// Ask the Inference API to hold the request until a cold model is loaded
+    /// instead of answering 503 straight away.
+    pub wait_for_model: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+            wait_for_model: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Jittered exponential backoff for the given retry (starting at 0).
+    ///
+    /// The delay is drawn uniformly between half and all of the exponential
+    /// delay, so that concurrent clients do not retry in lockstep.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        let half = exponential / 2;
+        half + half.mul_f64(jitter())
+    }
+
+    /// Server requested wait (Retry-After, estimated loading time), capped.
+    pub fn capped(&self, wait: Duration) -> Duration {
+        wait.min(self.max_delay)
+    }
+}
+
+/// A number in `[0, 1)`, good enough to spread retries apart.
+///
+/// Without a source of randomness the middle of the range is used.
+fn jitter() -> f64 {
+    getrandom::u32().map_or(0.5, |random| random as f64 / (u32::MAX as f64 + 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_with_jitter_and_is_capped() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            ..RetryPolicy::default()
+        };
+        for _ in 0..20 {
+            let first = policy.backoff(0);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let third = policy.backoff(2);
+            assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+            let late = policy.backoff(40);
+            assert!(late >= Duration::from_millis(500) && late <= Duration::from_secs(1));
+        }
+        assert_eq!(
+            policy.capped(Duration::from_secs(90)),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            policy.capped(Duration::from_millis(10)),
+            Duration::from_millis(10)
+        );
+    }
+
+    #[test]
+    fn jitter_stays_in_range_and_varies() {
+        let draws = (0..50).map(|_| jitter()).collect::<Vec<_>>();
+        assert!(draws.iter().all(|draw| (0.0..1.0).contains(draw)));
+        assert!(draws.iter().any(|draw| *draw != draws[0]));
+    }
+}
@@ -0,0 +1,578 @@
+use crate::backend::{BackendError, SentimentBackend};
+use crate::report::SentimentReport;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const RESULTS_FILE: &str = "results.ndjson";
+const STATS_FILE: &str = "stats.json";
+
+/// On-disk cache of analysis results, keyed by a hash of the model and of the text.
+///
+/// Entries are appended to an NDJSON log as soon as they are produced, so
+/// nothing is lost when the program exits abruptly. Later lines override
+/// earlier ones; the log is rewritten without expired, evicted and duplicate
+/// lines when it grows well past the number of live entries.
+pub struct ResultCache {
+    dir: PathBuf,
+    entries: HashMap<String, CacheEntry>,
+    ttl: Option<Duration>,
+    max_entries: usize,
+    log: Option<BufWriter<File>>,
+    /// Lines of the log that no longer correspond to a live entry.
+    stale_lines: usize,
+    /// Lookups not yet added to the lifetime counters.
+    hits: u64,
+    misses: u64,
+    /// Lookups since the cache was opened.
+    session: CacheStats,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CacheEntry {
+    key: String,
+    negative: f64,
+    neutral: f64,
+    positive: f64,
+    stars: Option<f64>,
+    /// Unix timestamp (seconds) of when the result was produced.
+    stored_at: i64,
+}
+
+/// Lifetime counters, kept next to the results.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Summary printed by `cache stats`.
+pub struct CacheSummary {
+    pub dir: PathBuf,
+    pub entries: usize,
+    pub expired: usize,
+    pub size_bytes: u64,
+    pub oldest: Option<i64>,
+    pub lifetime: CacheStats,
+}
+
+impl CacheEntry {
+    fn report(&self) -> SentimentReport {
+        SentimentReport {
+            neutral_score: self.neutral,
+            positive_score: self.positive,
+            negative_score: self.negative,
+            stars: self.stars,
+            chunks: Vec::new(),
+        }
+    }
+
+    fn is_expired(&self, ttl: Option<Duration>, now: i64) -> bool {
+        ttl.is_some_and(|ttl| now - self.stored_at > ttl.as_secs() as i64)
+    }
+}
+
+impl ResultCache {
+    /// Default location, in the per-user cache directory.
+    pub fn default_dir() -> PathBuf {
+        dirs::cache_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("sentiment_analyzer")
+    }
+
+    pub fn open(dir: &Path, ttl: Option<Duration>, max_entries: usize) -> Result<Self, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {e}", dir.display()))?;
+        let (entries, lines) = read_log(&dir.join(RESULTS_FILE));
+        let mut cache = ResultCache {
+            dir: dir.to_path_buf(),
+            entries,
+            ttl,
+            max_entries: max_entries.max(1),
+            log: None,
+            stale_lines: 0,
+            hits: 0,
+            misses: 0,
+            session: CacheStats::default(),
+        };
+
+        let now = Utc::now().timestamp();
+        cache.entries.retain(|_, entry| !entry.is_expired(ttl, now));
+        cache.evict_oldest();
+        cache.stale_lines = lines - cache.entries.len();
+        if cache.stale_lines > cache.entries.len() {
+            cache.compact()?;
+        }
+        cache.log = Some(BufWriter::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dir.join(RESULTS_FILE))
+                .map_err(|e| format!("Could not open the cache: {e}"))?,
+        ));
+        Ok(cache)
+    }
+
+    pub fn key(namespace: &str, text: &str) -> String {
+        let normalized = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        let digest = Sha256::new()
+            .chain_update(namespace.as_bytes())
+            .chain_update([0])
+            .chain_update(normalized.as_bytes())
+            .finalize();
+        digest.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<SentimentReport> {
+        let now = Utc::now().timestamp();
+        match self.entries.get(key) {
+            Some(entry) if !entry.is_expired(self.ttl, now) => {
+                self.hits += 1;
+                self.session.hits += 1;
+                Some(entry.report())
+            }
+            _ => {
+                self.misses += 1;
+                self.session.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, key: String, report: &SentimentReport) {
+        let entry = CacheEntry {
+            key: key.clone(),
+            negative: report.negative_score,
+            neutral: report.neutral_score,
+            positive: report.positive_score,
+            stars: report.stars,
+            stored_at: Utc::now().timestamp(),
+        };
+        if let Some(log) = self.log.as_mut() {
+            let written = serde_json::to_writer(&mut *log, &entry)
+                .map_err(|e| e.to_string())
+                .and_then(|_| writeln!(log).map_err(|e| e.to_string()))
+                .and_then(|_| log.flush().map_err(|e| e.to_string()));
+            if let Err(err) = written {
+                eprintln!("Could not write to the result cache, disabling it: {err}");
+                self.log = None;
+            }
+        }
+        if self.entries.insert(key, entry).is_some() {
+            self.stale_lines += 1;
+        }
+        self.stale_lines += self.evict_oldest();
+    }
+
+    /// Drops the oldest entries beyond `max_entries`, returns how many were dropped.
+    fn evict_oldest(&mut self) -> usize {
+        let excess = self.entries.len().saturating_sub(self.max_entries);
+        if excess == 0 {
+            return 0;
+        }
+        let mut by_age = self
+            .entries
+            .values()
+            .map(|entry| (entry.stored_at, entry.key.clone()))
+            .collect::<Vec<_>>();
+        by_age.sort();
+        for (_, key) in by_age.into_iter().take(excess) {
+            self.entries.remove(&key);
+        }
+        excess
+    }
+
+    /// Rewrites the log with the live entries only.
+    fn compact(&mut self) -> Result<(), String> {
+        let path = self.dir.join(RESULTS_FILE);
+        let temporary = self.dir.join(format!("{RESULTS_FILE}.tmp"));
+        let write = || -> std::io::Result<()> {
+            let mut writer = BufWriter::new(File::create(&temporary)?);
+            for entry in self.entries.values() {
+                serde_json::to_writer(&mut writer, entry)?;
+                writeln!(writer)?;
+            }
+            writer.flush()?;
+            fs::rename(&temporary, &path)
+        };
+        write().map_err(|e| format!("Could not compact the cache: {e}"))?;
+        self.stale_lines = 0;
+        Ok(())
+    }
+
+    /// Share of the lookups since the cache was opened that were hits.
+    pub fn session_hit_rate(&self) -> Option<f64> {
+        let lookups = self.session.hits + self.session.misses;
+        (lookups > 0).then(|| self.session.hits as f64 / lookups as f64)
+    }
+
+    /// Adds the hits and misses counted since the last call to the lifetime counters.
+    ///
+    /// Called on drop and at the end of a run (`SentimentBackend::finish`),
+    /// since the interactive menus leave through `std::process::exit`.
+    pub fn flush_stats(&mut self) {
+        if self.hits + self.misses == 0 {
+            return;
+        }
+        let path = self.dir.join(STATS_FILE);
+        let mut stats = read_stats(&path);
+        stats.hits += self.hits;
+        stats.misses += self.misses;
+        if let Ok(raw) = serde_json::to_string(&stats) {
+            let _ = fs::write(path, raw);
+        }
+        self.hits = 0;
+        self.misses = 0;
+    }
+
+    pub fn summary(dir: &Path, ttl: Option<Duration>) -> CacheSummary {
+        let path = dir.join(RESULTS_FILE);
+        let (entries, _) = read_log(&path);
+        let now = Utc::now().timestamp();
+        CacheSummary {
+            dir: dir.to_path_buf(),
+            entries: entries.len(),
+            expired: entries
+                .values()
+                .filter(|entry| entry.is_expired(ttl, now))
+                .count(),
+            size_bytes: fs::metadata(&path).map(|m| m.len()).unwrap_or(0),
+            oldest: entries.values().map(|entry| entry.stored_at).min(),
+            lifetime: read_stats(&dir.join(STATS_FILE)),
+        }
+    }
+
+    pub fn clear(dir: &Path) -> Result<(), String> {
+        for file in [RESULTS_FILE, STATS_FILE] {
+            match fs::remove_file(dir.join(file)) {
+                Ok(_) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.to_string()),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ResultCache {
+    fn drop(&mut self) {
+        if self.stale_lines > self.entries.len() {
+            let _ = self.compact();
+        }
+        self.flush_stats();
+    }
+}
+
+/// Live entries of the log (last line wins) and the number of lines read.
+fn read_log(path: &Path) -> (HashMap<String, CacheEntry>, usize) {
+    let mut entries = HashMap::new();
+    let mut lines = 0;
+    if let Ok(file) = File::open(path) {
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            // A torn last line (crash mid-write) is simply skipped.
+            if let Ok(entry) = serde_json::from_str::<CacheEntry>(&line) {
+                lines += 1;
+                entries.insert(entry.key.clone(), entry);
+            }
+        }
+    }
+    (entries, lines)
+}
+
+fn read_stats(path: &Path) -> CacheStats {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default()
+}
+
+/// Serves results from a `ResultCache` and only asks the wrapped backend for
+/// the texts it has not seen.
+pub struct CachedBackend {
+    inner: Arc<dyn SentimentBackend>,
+    cache: Mutex<ResultCache>,
+    /// Results of different models (or label mappings) must not mix.
+    namespace: String,
+}
+
+impl CachedBackend {
+    pub fn new(inner: Arc<dyn SentimentBackend>, cache: ResultCache) -> Self {
+        CachedBackend {
+            namespace: inner.describe(),
+            inner,
+            cache: Mutex::new(cache),
+        }
+    }
+
+    fn cache(&self) -> std::sync::MutexGuard<'_, ResultCache> {
+        // A panic while holding the lock cannot leave the map half updated.
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl SentimentBackend for CachedBackend {
+    fn describe(&self) -> String {
+        format!("{} (cached)", self.inner.describe())
+    }
+
+    fn model(&self) -> String {
+        self.inner.model()
+    }
+
+    fn labels(&self) -> Vec<String> {
+        self.inner.labels()
+    }
+
+    fn analyze(&self, text: &str) -> Result<SentimentReport, BackendError> {
+        let key = ResultCache::key(&self.namespace, text);
+        let cached = self.cache().get(&key);
+        let was_cached = cached.is_some();
+        let result = cached.map_or_else(|| self.inner.analyze(text), Ok);
+        let mut cache = self.cache();
+        if let (false, Ok(report)) = (was_cached, &result) {
+            cache.insert(key, report);
+        }
+        result
+    }
+
+    fn analyze_batch(&self, texts: &[String]) -> Vec<Result<SentimentReport, BackendError>> {
+        let keys = texts
+            .iter()
+            .map(|text| ResultCache::key(&self.namespace, text))
+            .collect::<Vec<_>>();
+        let mut results = {
+            let mut cache = self.cache();
+            keys.iter()
+                .map(|key| cache.get(key).map(Ok))
+                .collect::<Vec<_>>()
+        };
+
+        let missing = results
+            .iter()
+            .enumerate()
+            .filter(|(_, result)| result.is_none())
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            let missing_texts = missing
+                .iter()
+                .map(|&index| texts[index].clone())
+                .collect::<Vec<_>>();
+            let fresh = self.inner.analyze_batch(&missing_texts);
+            let mut cache = self.cache();
+            for (index, result) in missing.into_iter().zip(fresh) {
+                if let Ok(report) = &result {
+                    cache.insert(keys[index].clone(), report);
+                }
+                results[index] = Some(result);
+            }
+        }
+        results
+            .into_iter()
+            .map(|result| result.expect("every miss was analyzed"))
+            .collect()
+    }
+
+    fn replace_api_key(&self, api_key: &str) -> bool {
+        self.inner.replace_api_key(api_key)
+    }
+
+    fn usage_report(&self) -> Option<String> {
+        self.inner.usage_report()
+    }
+
+    fn cache_hit_rate(&self) -> Option<f64> {
+        self.cache().session_hit_rate()
+    }
+
+    fn finish(&self) {
+        self.cache().flush_stats();
+        self.inner.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{report, ScriptedBackend, TempDir};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn write_log(dir: &TempDir, entries: &[(&str, i64)]) {
+        let lines = entries
+            .iter()
+            .map(|(key, stored_at)| {
+                let entry = CacheEntry {
+                    key: key.to_string(),
+                    negative: 0.1,
+                    neutral: 0.2,
+                    positive: 0.7,
+                    stars: None,
+                    stored_at: *stored_at,
+                };
+                serde_json::to_string(&entry).unwrap() + "\n"
+            })
+            .collect::<String>();
+        fs::write(dir.join(RESULTS_FILE), lines).unwrap();
+    }
+
+    fn log_lines(dir: &TempDir) -> usize {
+        fs::read_to_string(dir.join(RESULTS_FILE))
+            .unwrap()
+            .lines()
+            .count()
+    }
+
+    #[test]
+    fn results_survive_a_restart() {
+        let dir = TempDir::new("cache-restart");
+        let key = ResultCache::key("model", "great  day\n");
+        {
+            let mut cache = ResultCache::open(dir.path(), None, 10).unwrap();
+            assert_eq!(cache.get(&key), None);
+            cache.insert(key.clone(), &report(0.1, 0.2, 0.7));
+        }
+        let mut cache = ResultCache::open(dir.path(), None, 10).unwrap();
+        assert_eq!(cache.get(&key), Some(report(0.1, 0.2, 0.7)));
+        assert_eq!(cache.session_hit_rate(), Some(1.0));
+    }
+
+    #[test]
+    fn keys_ignore_spacing_but_not_the_model() {
+        assert_eq!(
+            ResultCache::key("model", " great \t day "),
+            ResultCache::key("model", "great day")
+        );
+        assert_ne!(
+            ResultCache::key("model", "great day"),
+            ResultCache::key("other model", "great day")
+        );
+    }
+
+    #[test]
+    fn expired_entries_are_dropped() {
+        let dir = TempDir::new("cache-ttl");
+        let now = Utc::now().timestamp();
+        write_log(&dir, &[("old", now - 3_600), ("fresh", now - 10)]);
+
+        let mut cache = ResultCache::open(dir.path(), Some(Duration::from_secs(60)), 10).unwrap();
+        assert!(cache.get("old").is_none());
+        assert!(cache.get("fresh").is_some());
+
+        let mut forever = ResultCache::open(dir.path(), None, 10).unwrap();
+        assert!(forever.get("old").is_some());
+    }
+
+    #[test]
+    fn the_oldest_entries_are_evicted_first() {
+        let dir = TempDir::new("cache-eviction");
+        write_log(&dir, &[("b", 200), ("a", 100), ("c", 300)]);
+
+        let mut cache = ResultCache::open(dir.path(), None, 2).unwrap();
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_some());
+        assert!(cache.get("c").is_some());
+
+        cache.insert("d".to_string(), &report(0.0, 1.0, 0.0));
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+        assert!(cache.get("d").is_some());
+    }
+
+    #[test]
+    fn stale_lines_are_compacted_away() {
+        let dir = TempDir::new("cache-compaction");
+        let now = Utc::now().timestamp();
+        write_log(
+            &dir,
+            &[
+                ("a", now - 4),
+                ("a", now - 3),
+                ("a", now - 2),
+                ("a", now - 1),
+                ("b", now),
+            ],
+        );
+
+        let mut cache = ResultCache::open(dir.path(), None, 10).unwrap();
+        assert_eq!(log_lines(&dir), 2);
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_some());
+
+        // Overwriting entries appends, until the stale lines outnumber the live ones.
+        for _ in 0..3 {
+            cache.insert("a".to_string(), &report(0.0, 1.0, 0.0));
+        }
+        assert_eq!(log_lines(&dir), 5);
+        drop(cache);
+        assert_eq!(log_lines(&dir), 2);
+    }
+
+    #[test]
+    fn lifetime_stats_are_kept_next_to_the_results() {
+        let dir = TempDir::new("cache-stats");
+        {
+            let mut cache = ResultCache::open(dir.path(), None, 10).unwrap();
+            cache.insert("a".to_string(), &report(0.0, 1.0, 0.0));
+            cache.get("a");
+            cache.get("b");
+        }
+        let summary = ResultCache::summary(dir.path(), None);
+        assert_eq!(summary.entries, 1);
+        assert_eq!((summary.lifetime.hits, summary.lifetime.misses), (1, 1));
+
+        ResultCache::clear(dir.path()).unwrap();
+        assert_eq!(ResultCache::summary(dir.path(), None).entries, 0);
+    }
+
+    static ANALYZED: AtomicUsize = AtomicUsize::new(0);
+
+    fn counted(_: &str) -> Result<SentimentReport, BackendError> {
+        ANALYZED.fetch_add(1, Ordering::SeqCst);
+        Ok(report(0.1, 0.2, 0.7))
+    }
+
+    #[test]
+    fn only_misses_reach_the_wrapped_backend() {
+        let dir = TempDir::new("cache-backend");
+        let backend = CachedBackend::new(
+            Arc::new(ScriptedBackend(counted)),
+            ResultCache::open(dir.path(), None, 10).unwrap(),
+        );
+        let texts = ["one", "two"].map(String::from);
+        assert!(backend.analyze_batch(&texts).iter().all(Result::is_ok));
+        assert_eq!(ANALYZED.load(Ordering::SeqCst), 2);
+
+        let texts = ["two", "three", "one"].map(String::from);
+        let results = backend.analyze_batch(&texts);
+        assert_eq!(results.len(), 3);
+        assert_eq!(ANALYZED.load(Ordering::SeqCst), 3);
+        assert_eq!(backend.cache_hit_rate(), Some(2.0 / 5.0));
+    }
+
+    #[test]
+    fn stats_are_written_at_the_end_of_the_run_only() {
+        let dir = TempDir::new("cache-finish");
+        let backend = CachedBackend::new(
+            Arc::new(ScriptedBackend(|_| Ok(report(0.1, 0.2, 0.7)))),
+            ResultCache::open(dir.path(), None, 10).unwrap(),
+        );
+        backend.analyze("one").unwrap();
+        backend.analyze("one").unwrap();
+        assert!(!dir.join(STATS_FILE).exists());
+
+        backend.finish();
+        let lifetime = ResultCache::summary(dir.path(), None).lifetime;
+        assert_eq!((lifetime.hits, lifetime.misses), (1, 1));
+        // Dropping the cache afterwards does not count the lookups twice.
+        drop(backend);
+        let lifetime = ResultCache::summary(dir.path(), None).lifetime;
+        assert_eq!((lifetime.hits, lifetime.misses), (1, 1));
+    }
+}
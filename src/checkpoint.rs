@@ -0,0 +1,106 @@
+use crate::dataset::InputFormat;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Version 2 added the `sentiment_chunks` column to enriched CSV files.
+const FORMAT_VERSION: u32 = 2;
+
+/// What a batch job is made of. A checkpoint is only resumed by the same job,
+/// anything else would mix rows or models in one output.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct JobSettings {
+    pub input: PathBuf,
+    /// Size of the input when the job started, to notice an edited file.
+    pub input_bytes: u64,
+    pub rows: usize,
+    pub format: InputFormat,
+    /// Column or JSON pointer the texts were taken from.
+    pub text_field: String,
+    pub model: String,
+}
+
+/// Progress of an `analyze-file` job, saved next to its output.
+///
+/// The output holds exactly `rows_written` rows in its first `output_bytes`
+/// bytes. Anything written after that was not checkpointed and is dropped on
+/// resume.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Checkpoint {
+    version: u32,
+    pub job: JobSettings,
+    pub rows_written: usize,
+    pub output_bytes: u64,
+    /// Rows that were written with an error instead of a sentiment.
+    pub failed: BTreeSet<usize>,
+    updated_at: String,
+}
+
+impl Checkpoint {
+    pub fn new(job: JobSettings) -> Self {
+        Checkpoint {
+            version: FORMAT_VERSION,
+            job,
+            rows_written: 0,
+            output_bytes: 0,
+            failed: BTreeSet::new(),
+            updated_at: Utc::now().to_rfc3339(),
+        }
+    }
+
+    /// `results.csv` is checkpointed into `results.csv.checkpoint`.
+    pub fn path_for(output: &Path) -> PathBuf {
+        let mut path = output.as_os_str().to_owned();
+        path.push(".checkpoint");
+        PathBuf::from(path)
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        let raw = match fs::read_to_string(path) {
+            Ok(raw) => raw,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(format!("Could not read {}: {err}", path.display())),
+        };
+        let checkpoint: Checkpoint = serde_json::from_str(&raw)
+            .map_err(|e| format!("Corrupted checkpoint {}: {e}", path.display()))?;
+        if checkpoint.version != FORMAT_VERSION {
+            return Err(format!(
+                "Unsupported checkpoint {} (version {})",
+                path.display(),
+                checkpoint.version
+            ));
+        }
+        Ok(Some(checkpoint))
+    }
+
+    /// Fails when the checkpoint belongs to another job.
+    pub fn check(&self, job: &JobSettings) -> Result<(), String> {
+        let mismatch = if self.job.input_bytes != job.input_bytes || self.job.rows != job.rows {
+            "the input file changed since the checkpoint was made"
+        } else if self.job.format != job.format || self.job.text_field != job.text_field {
+            "the checkpoint took the texts from another field"
+        } else if self.job.model != job.model {
+            "the checkpoint was made with another model"
+        } else {
+            return Ok(());
+        };
+        Err(format!("Cannot resume, {mismatch}"))
+    }
+
+    /// Replaces the file atomically, a crash while saving keeps the previous
+    /// checkpoint.
+    pub fn save(&mut self, path: &Path) -> Result<(), String> {
+        self.updated_at = Utc::now().to_rfc3339();
+        let raw = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+        fs::File::create(&temporary)
+            .and_then(|mut file| file.write_all(raw.as_bytes()).and_then(|_| file.sync_all()))
+            .and_then(|_| fs::rename(&temporary, path))
+            .map_err(|e| format!("Could not write {}: {e}", path.display()))
+    }
+}
@@ -0,0 +1,546 @@
+use crate::backend::{BackendError, SentimentBackend};
+use crate::report::{ChunkScore, SentimentReport};
+use clap::ValueEnum;
+use std::sync::Arc;
+
+/// How the scores of the chunks of a long text are combined into one report.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChunkAggregation {
+    /// Mean of the chunk scores weighted by the length of each chunk.
+    #[default]
+    WeightedMean,
+    /// Mean of the chunk scores, every chunk counting the same.
+    Mean,
+    /// Scores of the most negative chunk, so that a hostile paragraph is not
+    /// diluted by the rest of the text.
+    MaxNegative,
+}
+
+/// Characters ending a sentence when followed by whitespace (or the end).
+const SENTENCE_ENDS: [char; 4] = ['.', '!', '?', '…'];
+/// Characters ending a sentence wherever they are, in scripts written
+/// without spaces.
+const FULL_WIDTH_SENTENCE_ENDS: [char; 3] = ['。', '！', '？'];
+/// Words whose period does not end a sentence, compared in lowercase.
+const ABBREVIATIONS: [&str; 12] = [
+    "dr", "mr", "mrs", "ms", "prof", "st", "jr", "sr", "vs", "e.g", "i.e", "cf",
+];
+
+/// Splits texts longer than the model accepts into sentence aligned chunks,
+/// scores every chunk and aggregates their scores.
+///
+/// Models like twitter-roberta-base-sentiment stop at 512 tokens: past that
+/// the Inference API truncates the text or rejects it. Shorter texts are
+/// passed through untouched. The budget is in estimated tokens (see
+/// `estimate_tokens`), so it should stay well under the real limit.
+pub struct ChunkingBackend {
+    inner: Arc<dyn SentimentBackend>,
+    max_tokens: usize,
+    aggregation: ChunkAggregation,
+}
+
+impl ChunkingBackend {
+    pub fn new(
+        inner: Arc<dyn SentimentBackend>,
+        max_tokens: usize,
+        aggregation: ChunkAggregation,
+    ) -> Self {
+        ChunkingBackend {
+            inner,
+            max_tokens: max_tokens.max(1),
+            aggregation,
+        }
+    }
+
+    /// The chunks of `text`, or `None` when it fits in one request.
+    fn chunks_of(&self, text: &str) -> Option<Vec<String>> {
+        (estimate_tokens(text) > self.max_tokens).then(|| split(text, self.max_tokens))
+    }
+
+    fn aggregate(
+        &self,
+        chunks: Vec<String>,
+        results: Vec<Result<SentimentReport, BackendError>>,
+    ) -> Result<SentimentReport, BackendError> {
+        let chunks = chunks
+            .into_iter()
+            .zip(results)
+            .map(|(text, result)| {
+                result.map(|report| ChunkScore {
+                    tokens: estimate_tokens(&text),
+                    text,
+                    report,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(aggregate(chunks, self.aggregation))
+    }
+}
+
+impl SentimentBackend for ChunkingBackend {
+    fn describe(&self) -> String {
+        format!(
+            "{}\nLong texts: chunks of at most {} tokens, {}",
+            self.inner.describe(),
+            self.max_tokens,
+            self.aggregation
+                .to_possible_value()
+                .map(|value| value.get_name().to_string())
+                .unwrap_or_default()
+        )
+    }
+
+    fn model(&self) -> String {
+        self.inner.model()
+    }
+
+    fn labels(&self) -> Vec<String> {
+        self.inner.labels()
+    }
+
+    fn analyze(&self, text: &str) -> Result<SentimentReport, BackendError> {
+        match self.chunks_of(text) {
+            None => self.inner.analyze(text),
+            Some(chunks) => {
+                let results = self.inner.analyze_batch(&chunks);
+                self.aggregate(chunks, results)
+            }
+        }
+    }
+
+    /// The chunks of every text go to the wrapped backend in a single batch.
+    fn analyze_batch(&self, texts: &[String]) -> Vec<Result<SentimentReport, BackendError>> {
+        let plans = texts
+            .iter()
+            .map(|text| self.chunks_of(text))
+            .collect::<Vec<_>>();
+        let requests = texts
+            .iter()
+            .zip(&plans)
+            .flat_map(|(text, chunks)| match chunks {
+                Some(chunks) => chunks.clone(),
+                None => vec![text.clone()],
+            })
+            .collect::<Vec<_>>();
+        let mut results = self.inner.analyze_batch(&requests).into_iter();
+
+        plans
+            .into_iter()
+            .map(|chunks| match chunks {
+                None => results
+                    .next()
+                    .unwrap_or_else(|| Err(BackendError::Transport("missing result".to_string()))),
+                Some(chunks) => {
+                    let chunk_results = results.by_ref().take(chunks.len()).collect::<Vec<_>>();
+                    self.aggregate(chunks, chunk_results)
+                }
+            })
+            .collect()
+    }
+
+    fn replace_api_key(&self, api_key: &str) -> bool {
+        self.inner.replace_api_key(api_key)
+    }
+
+    fn usage_report(&self) -> Option<String> {
+        self.inner.usage_report()
+    }
+
+    fn cache_hit_rate(&self) -> Option<f64> {
+        self.inner.cache_hit_rate()
+    }
+
+    fn finish(&self) {
+        self.inner.finish();
+    }
+}
+
+/// Rough token count of `text` for RoBERTa-like BPE tokenizers.
+///
+/// English words average a bit over one token (4/3 is used), while words of
+/// more than `LONG_WORD_LETTERS` letters (URLs, hashes, identifiers) count a
+/// token per 4 letters. ASCII punctuation is a token of its own and every
+/// non-ASCII character (accents, emoji, CJK) is counted as one more token.
+/// It errs on the high side.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.split_whitespace().map(word_tokens).sum::<f64>().ceil() as usize
+}
+
+/// Words longer than this are split into several tokens by BPE tokenizers.
+const LONG_WORD_LETTERS: usize = 8;
+
+fn word_tokens(word: &str) -> f64 {
+    let mut cost = WordCost::default();
+    word.chars().for_each(|c| cost.push(c));
+    cost.tokens()
+}
+
+/// Running estimate of a word, read one character at a time.
+#[derive(Default, Clone, Copy)]
+struct WordCost {
+    letters: usize,
+    /// Punctuation and non-ASCII characters, a token each.
+    others: usize,
+}
+
+impl WordCost {
+    fn push(&mut self, c: char) {
+        if c.is_ascii_alphanumeric() {
+            self.letters += 1;
+        } else if c.is_ascii_punctuation() || !c.is_ascii() {
+            self.others += 1;
+        }
+    }
+
+    fn tokens(&self) -> f64 {
+        let letters = if self.letters > LONG_WORD_LETTERS {
+            self.letters.div_ceil(4) as f64
+        } else {
+            4.0 / 3.0
+        };
+        letters + self.others as f64
+    }
+}
+
+/// Splits `text` into chunks of whole sentences of at most `max_tokens`
+/// estimated tokens each. Sentences longer than that are cut between words,
+/// and words longer than that (URLs, unspaced scripts) between characters.
+pub fn split(text: &str, max_tokens: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_tokens = 0;
+    for piece in sentences(text).flat_map(|sentence| fit(sentence, max_tokens)) {
+        let tokens = estimate_tokens(&piece);
+        if current_tokens + tokens > max_tokens && !current.is_empty() {
+            chunks.push(std::mem::take(&mut current));
+            current_tokens = 0;
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(&piece);
+        current_tokens += tokens;
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// Sentences of `text`, line breaks ending a sentence as well.
+fn sentences(text: &str) -> impl Iterator<Item = &str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        let next_is_break = chars.peek().is_none_or(|(_, next)| next.is_whitespace());
+        if c == '\n'
+            || FULL_WIDTH_SENTENCE_ENDS.contains(&c)
+            || (SENTENCE_ENDS.contains(&c)
+                && next_is_break
+                && !(c == '.' && is_abbreviation(&text[start..index])))
+        {
+            let end = index + c.len_utf8();
+            sentences.push(&text[start..end]);
+            start = end;
+        }
+    }
+    sentences.push(&text[start..]);
+    sentences
+        .into_iter()
+        .map(str::trim)
+        .filter(|sentence| !sentence.is_empty())
+}
+
+/// Whether the last word of `before`, followed by a period, is an abbreviation.
+fn is_abbreviation(before: &str) -> bool {
+    let word = before
+        .rsplit(char::is_whitespace)
+        .next()
+        .unwrap_or_default();
+    let word = word.trim_start_matches(|c: char| !c.is_alphanumeric());
+    ABBREVIATIONS.contains(&word.to_lowercase().as_str())
+}
+
+/// Cuts a sentence that does not fit in `max_tokens` into pieces that do.
+fn fit(sentence: &str, max_tokens: usize) -> Vec<String> {
+    if estimate_tokens(sentence) <= max_tokens {
+        return vec![sentence.to_string()];
+    }
+    let max_tokens = max_tokens as f64;
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    let mut current_tokens = 0.0;
+    for word in sentence.split_whitespace() {
+        let tokens = word_tokens(word);
+        if (current_tokens + tokens > max_tokens || tokens > max_tokens) && !current.is_empty() {
+            pieces.push(std::mem::take(&mut current));
+            current_tokens = 0.0;
+        }
+        if tokens > max_tokens {
+            pieces.extend(cut_word(word, max_tokens));
+            continue;
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+        current_tokens += tokens;
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+    pieces
+}
+
+/// Cuts a word between characters into pieces as large as `max_tokens` allows,
+/// so that they are not joined back together (with spaces) into one chunk.
+fn cut_word(word: &str, max_tokens: f64) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut piece = String::new();
+    let mut cost = WordCost::default();
+    for c in word.chars() {
+        let mut grown = cost;
+        grown.push(c);
+        if grown.tokens() > max_tokens && !piece.is_empty() {
+            pieces.push(std::mem::take(&mut piece));
+            grown = WordCost::default();
+            grown.push(c);
+        }
+        cost = grown;
+        piece.push(c);
+    }
+    if !piece.is_empty() {
+        pieces.push(piece);
+    }
+    pieces
+}
+
+/// One report out of the reports of the chunks, which are kept in it.
+fn aggregate(chunks: Vec<ChunkScore>, aggregation: ChunkAggregation) -> SentimentReport {
+    let mut report = match aggregation {
+        ChunkAggregation::MaxNegative => chunks
+            .iter()
+            .max_by(|a, b| a.report.negative_score.total_cmp(&b.report.negative_score))
+            .map(|chunk| chunk.report.clone())
+            .unwrap_or_else(neutral_report),
+        ChunkAggregation::WeightedMean | ChunkAggregation::Mean => {
+            let weight = |chunk: &ChunkScore| match aggregation {
+                ChunkAggregation::WeightedMean => chunk.tokens.max(1) as f64,
+                _ => 1.0,
+            };
+            let total = chunks.iter().map(weight).sum::<f64>();
+            let mean = |score: fn(&SentimentReport) -> f64| {
+                chunks
+                    .iter()
+                    .map(|chunk| weight(chunk) * score(&chunk.report))
+                    .sum::<f64>()
+                    / total
+            };
+            if total == 0.0 {
+                neutral_report()
+            } else {
+                SentimentReport {
+                    neutral_score: mean(|report| report.neutral_score),
+                    positive_score: mean(|report| report.positive_score),
+                    negative_score: mean(|report| report.negative_score),
+                    // Only meaningful when every chunk has a rating.
+                    stars: chunks
+                        .iter()
+                        .all(|chunk| chunk.report.stars.is_some())
+                        .then(|| mean(|report| report.stars.unwrap_or_default())),
+                    chunks: Vec::new(),
+                }
+            }
+        }
+    };
+    report.chunks = chunks;
+    report
+}
+
+fn neutral_report() -> SentimentReport {
+    SentimentReport {
+        neutral_score: 1.0,
+        positive_score: 0.0,
+        negative_score: 0.0,
+        stars: None,
+        chunks: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{report, ScriptedBackend};
+
+    fn chunk(text: &str, tokens: usize, report: SentimentReport) -> ChunkScore {
+        ChunkScore {
+            text: text.to_string(),
+            tokens,
+            report,
+        }
+    }
+
+    #[test]
+    fn tokens_are_overestimated_rather_than_under() {
+        assert_eq!(estimate_tokens(""), 0);
+        // 3 words and a full stop.
+        assert_eq!(estimate_tokens("This is fine."), 5);
+        // Every non-ASCII character counts on top of its word.
+        assert_eq!(estimate_tokens("café"), 3);
+        assert_eq!(estimate_tokens("日本語"), 5);
+    }
+
+    #[test]
+    fn chunks_hold_whole_sentences() {
+        let text = "One two three. Four five six! Seven eight nine? Ten.";
+        let chunks = split(text, 10);
+        assert_eq!(
+            chunks,
+            ["One two three. Four five six!", "Seven eight nine? Ten."]
+        );
+        assert!(chunks.iter().all(|chunk| estimate_tokens(chunk) <= 10));
+    }
+
+    #[test]
+    fn abbreviations_and_decimals_do_not_end_sentences() {
+        assert_eq!(
+            sentences("Up 3.5% on Friday.Shares rose").collect::<Vec<_>>(),
+            ["Up 3.5% on Friday.Shares rose"]
+        );
+        assert_eq!(
+            sentences("Dr. Smith liked it, e.g. the cast. Mr. Jones did not. Fine.")
+                .collect::<Vec<_>>(),
+            [
+                "Dr. Smith liked it, e.g. the cast.",
+                "Mr. Jones did not.",
+                "Fine."
+            ]
+        );
+        assert_eq!(
+            sentences("First line\nsecond line. 日本。語").collect::<Vec<_>>(),
+            ["First line", "second line.", "日本。", "語"]
+        );
+    }
+
+    #[test]
+    fn long_sentences_are_cut_between_words_then_characters() {
+        let sentence = "word ".repeat(20);
+        let chunks = split(&sentence, 10);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| estimate_tokens(chunk) <= 10));
+        assert_eq!(chunks.join(" "), sentence.trim());
+
+        let url = format!("https://example.com/{}", "a".repeat(100));
+        let chunks = split(&url, 10);
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.concat(), url);
+        assert!(
+            chunks.iter().all(|chunk| estimate_tokens(chunk) <= 10),
+            "{chunks:?}"
+        );
+    }
+
+    #[test]
+    fn the_weighted_mean_favours_longer_chunks() {
+        let aggregated = aggregate(
+            vec![
+                chunk("short", 1, report(1.0, 0.0, 0.0)),
+                chunk("long", 3, report(0.0, 0.0, 1.0)),
+            ],
+            ChunkAggregation::WeightedMean,
+        );
+        assert_eq!(aggregated.negative_score, 0.25);
+        assert_eq!(aggregated.positive_score, 0.75);
+        assert_eq!(aggregated.chunks.len(), 2);
+
+        let mean = aggregate(aggregated.chunks, ChunkAggregation::Mean);
+        assert_eq!(mean.negative_score, 0.5);
+        assert_eq!(mean.positive_score, 0.5);
+    }
+
+    #[test]
+    fn max_negative_keeps_the_most_negative_chunk() {
+        let aggregated = aggregate(
+            vec![
+                chunk("fine", 10, report(0.1, 0.1, 0.8)),
+                chunk("hostile", 1, report(0.9, 0.1, 0.0)),
+                chunk("meh", 10, report(0.3, 0.7, 0.0)),
+            ],
+            ChunkAggregation::MaxNegative,
+        );
+        assert_eq!(aggregated.negative_score, 0.9);
+        assert_eq!(aggregated.chunks.len(), 3);
+    }
+
+    #[test]
+    fn stars_are_only_averaged_when_every_chunk_has_them() {
+        let rated = |stars| SentimentReport {
+            stars,
+            ..report(0.0, 1.0, 0.0)
+        };
+        let aggregated = aggregate(
+            vec![
+                chunk("a", 1, rated(Some(2.0))),
+                chunk("b", 1, rated(Some(4.0))),
+            ],
+            ChunkAggregation::Mean,
+        );
+        assert_eq!(aggregated.stars, Some(3.0));
+        let aggregated = aggregate(
+            vec![chunk("a", 1, rated(Some(2.0))), chunk("b", 1, rated(None))],
+            ChunkAggregation::Mean,
+        );
+        assert_eq!(aggregated.stars, None);
+    }
+
+    fn by_content(text: &str) -> Result<SentimentReport, BackendError> {
+        if text.contains("broken") {
+            Err(BackendError::Transport("down".to_string()))
+        } else if text.contains("bad") {
+            Ok(report(1.0, 0.0, 0.0))
+        } else {
+            Ok(report(0.0, 0.0, 1.0))
+        }
+    }
+
+    #[test]
+    fn only_long_texts_are_chunked() {
+        let backend = ChunkingBackend::new(
+            Arc::new(ScriptedBackend(by_content)),
+            8,
+            ChunkAggregation::Mean,
+        );
+        let short = backend.analyze("all good").unwrap();
+        assert!(short.chunks.is_empty());
+
+        let long = backend
+            .analyze("This part is good. This part is bad. This part is good.")
+            .unwrap();
+        assert_eq!(long.chunks.len(), 3);
+        assert!((long.positive_score - 2.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn batches_are_reassembled_per_text() {
+        let backend = ChunkingBackend::new(
+            Arc::new(ScriptedBackend(by_content)),
+            8,
+            ChunkAggregation::Mean,
+        );
+        let texts = [
+            "short and bad",
+            "This part is good. This part is bad.",
+            "fine",
+            "This part is good. This part is broken.",
+        ]
+        .map(String::from);
+        let results = backend.analyze_batch(&texts);
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].as_ref().unwrap().negative_score, 1.0);
+        assert_eq!(results[1].as_ref().unwrap().chunks.len(), 2);
+        assert_eq!(results[2].as_ref().unwrap().positive_score, 1.0);
+        // One failed chunk fails its text.
+        assert!(results[3].is_err());
+    }
+}
@@ -0,0 +1,1113 @@
+use crate::backend::{
+    ApiKeyList, BackendKind, BackendSettings, KeyRotation, RetryPolicy, SentimentBackend,
+    HF_MODELS_URL,
+};
+use crate::cache::{CachedBackend, ResultCache};
+use crate::checkpoint::Checkpoint;
+use crate::chunking::{ChunkAggregation, ChunkingBackend};
+use crate::config;
+use crate::credentials::{
+    hf_cli_token_paths, locate_api_key, prompt_user_for_api_key, remove_saved_keys,
+    resolve_api_key, save_api_key_to_file, validate_api_key, KeyOrigin, KeyValidationError,
+    ReauthenticatingBackend, API_KEY_ENV_VARS, HF_HUB_URL,
+};
+use crate::dataset::{Dataset, InputFormat, TextField};
+use crate::feed::FeedWatcher;
+use crate::keystore;
+use crate::labels::LabelMapping;
+use crate::output::{OutputFormat, ResultWriter};
+use crate::pipeline::{Delivery, Pipeline};
+use crate::progress::{self, Progress, Spinner};
+use crate::render::{Renderer, Tint};
+use crate::report::SentimentReport;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use reqwest::blocking::Client;
+use std::fs::File;
+use std::io::{BufRead, BufReader, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Sentiment analysis of user text and RSS/Atom feeds through the HuggingFace Inference API.
+///
+/// Without a subcommand the interactive menus are shown.
+#[derive(Parser, Debug)]
+#[command(name = "sentiment_analyzer", version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Configuration file (defaults to config.toml in the user configuration directory).
+    #[arg(long, global = true, env = "SENTIMENT_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Profile of the configuration file to use on top of its top level settings.
+    #[arg(long, global = true, env = "SENTIMENT_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Backend running the analysis (asked for in the menus when not given).
+    #[arg(long, global = true, value_enum, env = "SENTIMENT_BACKEND")]
+    pub backend: Option<BackendKind>,
+
+    /// Directory holding model.onnx, tokenizer.json and config.json (local backend).
+    #[arg(long, global = true, env = "SENTIMENT_MODEL_DIR")]
+    pub model_dir: Option<PathBuf>,
+
+    /// HuggingFace model id to run the analysis with.
+    #[arg(long, global = true, env = "SENTIMENT_MODEL")]
+    pub model: Option<String>,
+
+    /// Label mapping overriding the built-in one of the model,
+    /// e.g. `LABEL_0=negative,LABEL_1=neutral,LABEL_2=positive` or `1 star=1 star,...`.
+    #[arg(long, global = true, env = "SENTIMENT_LABELS", value_parser = LabelMapping::parse)]
+    pub labels: Option<LabelMapping>,
+
+    /// Base URL of the Inference API, model ids are appended to it.
+    #[arg(long, global = true, env = "SENTIMENT_ENDPOINT", default_value = HF_MODELS_URL)]
+    pub endpoint: String,
+
+    /// Base URL of the Hugging Face hub, whose whoami endpoint validates API keys.
+    #[arg(long, global = true, env = "SENTIMENT_HUB_URL", default_value = HF_HUB_URL)]
+    pub hub_url: String,
+
+    /// Seconds before a request to the Inference API is abandoned, `0` waits forever.
+    #[arg(long, global = true, env = "SENTIMENT_TIMEOUT", default_value_t = DEFAULT_TIMEOUT_SECS)]
+    pub timeout: u64,
+
+    /// How results are printed.
+    #[arg(long, global = true, value_enum, env = "SENTIMENT_FORMAT", default_value_t = OutputFormat::Human)]
+    pub format: OutputFormat,
+
+    /// File the results are written to instead of stdout.
+    #[arg(long, short, global = true, env = "SENTIMENT_OUTPUT")]
+    pub output: Option<PathBuf>,
+
+    /// Retries of a failed request (transient errors, rate limits, loading model).
+    #[arg(long, global = true, default_value_t = RetryPolicy::default().max_retries)]
+    pub retries: u32,
+
+    /// Texts sent per Inference API request when analyzing several at once.
+    #[arg(long, global = true, default_value_t = DEFAULT_BATCH_SIZE, value_parser = clap::value_parser!(u32).range(1..))]
+    pub batch_size: u32,
+
+    /// Requests (of --batch-size texts each) in flight at once for files and feeds.
+    #[arg(long, global = true, default_value_t = DEFAULT_CONCURRENCY, value_parser = clap::value_parser!(u32).range(1..))]
+    pub concurrency: u32,
+
+    /// Print results as soon as they are ready instead of in input order.
+    #[arg(long, global = true)]
+    pub unordered: bool,
+
+    /// Texts longer than this many (estimated) tokens are split into sentence
+    /// aligned chunks scored separately, `0` never splits them.
+    #[arg(long, global = true, default_value_t = DEFAULT_CHUNK_TOKENS)]
+    pub chunk_tokens: u32,
+
+    /// How the scores of the chunks of a long text are combined.
+    #[arg(long, global = true, value_enum, default_value_t = ChunkAggregation::default())]
+    pub chunk_aggregation: ChunkAggregation,
+
+    /// Ask the Inference API to wait for a cold model to load instead of failing with 503.
+    #[arg(long, global = true)]
+    pub wait_for_model: bool,
+
+    /// Where the API key is read from.
+    #[arg(long, global = true, value_enum, env = "SENTIMENT_KEY_SOURCE", default_value_t = KeySource::Auto)]
+    pub key_source: KeySource,
+
+    /// File of API keys (one per line) used in turn instead of a single key.
+    #[arg(long, global = true, env = "SENTIMENT_KEY_POOL", value_parser = ApiKeyList::from_file)]
+    pub key_pool: Option<ApiKeyList>,
+
+    /// How the keys of --key-pool take turns.
+    #[arg(long, global = true, value_enum, default_value_t = KeyRotation::default())]
+    pub key_rotation: KeyRotation,
+
+    /// Seconds a rate limited key rests when the API does not say how long.
+    #[arg(long, global = true, default_value_t = DEFAULT_KEY_COOLDOWN_SECS)]
+    pub key_cooldown: u64,
+
+    /// File the API key is saved to, encrypted, and read from.
+    #[arg(long, global = true, env = "SENTIMENT_KEY_FILE", default_value_os_t = keystore::default_path())]
+    pub key_file: PathBuf,
+
+    /// Always ask the backend, neither reading nor storing cached results.
+    #[arg(long, global = true, env = "SENTIMENT_NO_CACHE")]
+    pub no_cache: bool,
+
+    /// Seconds a cached result stays valid, `0` keeps results forever.
+    #[arg(long, global = true, default_value_t = DEFAULT_CACHE_TTL_SECS)]
+    pub cache_ttl: u64,
+
+    /// Results kept in the cache, the oldest ones are evicted first.
+    #[arg(long, global = true, default_value_t = DEFAULT_CACHE_MAX_ENTRIES, value_parser = clap::value_parser!(u64).range(1..))]
+    pub cache_max_entries: u64,
+
+    /// Directory of the result cache (defaults to the user cache directory).
+    #[arg(long, global = true, env = "SENTIMENT_CACHE_DIR")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Disable coloured output (the NO_COLOR environment variable is honoured as well).
+    #[arg(long, global = true)]
+    pub no_color: bool,
+
+    /// Never draw progress bars and spinners (they are only drawn on a terminal).
+    #[arg(long, global = true, env = "SENTIMENT_NO_PROGRESS")]
+    pub no_progress: bool,
+
+    /// Analyze the records piped on stdin, one result per record, until EOF.
+    ///
+    /// No menu is shown: the backend defaults to huggingface and the API key
+    /// is looked up like for a subcommand.
+    #[arg(long)]
+    pub stdin: bool,
+
+    /// Records on stdin are separated by NUL bytes instead of newlines.
+    #[arg(long, short = '0', requires = "stdin")]
+    pub null: bool,
+
+    /// Feeds followed when none is given, from the configuration file.
+    #[arg(skip)]
+    pub feeds: Vec<String>,
+
+    /// Default of `feed --interval`, from the configuration file.
+    #[arg(skip = DEFAULT_POLL_INTERVAL_SECS)]
+    pub poll_interval: u64,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Analyze a single text, or every non-empty line of a file.
+    Analyze {
+        /// Text to analyze.
+        #[arg(required_unless_present = "file", conflicts_with = "file")]
+        text: Option<String>,
+        /// Analyze every non-empty line of this file instead.
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Score every row of a CSV, JSONL or text file into an enriched copy of it.
+    ///
+    /// The sentiment is appended to each row (`sentiment_*` columns, or a
+    /// `sentiment` object for JSONL), plain text being enriched into CSV.
+    /// `--output` defaults to `<file>.sentiment.<csv|jsonl>`, `--format` is
+    /// not used.
+    AnalyzeFile {
+        path: PathBuf,
+        /// Layout of the file [default: from its extension, plain text otherwise].
+        #[arg(long)]
+        input_format: Option<InputFormat>,
+        /// CSV column holding the text, by header name or 1-based position.
+        #[arg(long, default_value = "text")]
+        column: String,
+        /// JSON pointer to the text in each JSONL object.
+        #[arg(long, default_value = "/text")]
+        pointer: String,
+        /// Continue an interrupted run from its checkpoint (`<output>.checkpoint`),
+        /// analyzing again the rows that failed.
+        #[arg(long)]
+        resume: bool,
+        /// Rows written between two checkpoints.
+        #[arg(long, default_value_t = DEFAULT_CHECKPOINT_ROWS, value_parser = clap::value_parser!(u64).range(1..))]
+        checkpoint_every: u64,
+    },
+    /// Follow one or more RSS/Atom feeds (URLs or local files).
+    Feed {
+        /// Feeds to follow, those of the configuration file when none is given.
+        sources: Vec<String>,
+        /// Seconds to wait between two polls [default: 300, or the configured interval].
+        #[arg(long)]
+        interval: Option<u64>,
+        /// Poll the feeds a single time and exit.
+        #[arg(long)]
+        once: bool,
+    },
+    /// Show the backend in use and the labels its model emits.
+    Info,
+    /// Manage the saved API key.
+    Key {
+        #[command(subcommand)]
+        action: KeyAction,
+    },
+    /// Show the configuration file in use and its profiles.
+    Config,
+    /// Inspect or empty the result cache.
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheAction {
+    /// Show the size of the cache and its lifetime hit rate.
+    Stats,
+    /// Delete every cached result.
+    Clear,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum KeyAction {
+    /// Validate and save a key (prompted for when not given).
+    Set { key: Option<String> },
+    /// Delete the saved key.
+    Clear,
+    /// Check that the active key is accepted by the API.
+    Check,
+    /// Show where the active key comes from, without printing it.
+    Source,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeySource {
+    /// HF_TOKEN/HUGGING_FACE_HUB_TOKEN, then the saved key, then the Hugging Face CLI token.
+    Auto,
+    /// Only the saved key.
+    File,
+    /// Only the HF_TOKEN or HUGGING_FACE_HUB_TOKEN environment variables.
+    Env,
+    /// Only the token file written by `huggingface-cli login`.
+    HfCli,
+    /// Always ask for the key.
+    Prompt,
+}
+
+impl Cli {
+    /// Checks what clap cannot express: `--stdin` replaces the menus, so no
+    /// subcommand can go with it.
+    pub fn check_conflicts(&self) -> Result<(), clap::Error> {
+        if self.stdin && self.command.is_some() {
+            return Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "--stdin cannot be combined with a subcommand",
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn backend_settings(&self, api_key: String) -> BackendSettings {
+        BackendSettings {
+            model_id: self
+                .model
+                .clone()
+                .unwrap_or_else(|| DEFAULT_MODEL_ID.to_string()),
+            endpoint: self.endpoint.clone(),
+            api_key,
+            model_dir: self.model_dir.clone(),
+            key_pool: self.key_pool.clone(),
+            key_rotation: self.key_rotation,
+            key_cooldown: Duration::from_secs(self.key_cooldown),
+            label_mapping: self.labels.clone(),
+            retry_policy: RetryPolicy {
+                max_retries: self.retries,
+                wait_for_model: self.wait_for_model,
+                ..RetryPolicy::default()
+            },
+            batch_size: self.batch_size as usize,
+        }
+    }
+}
+
+impl Cli {
+    fn cache_dir(&self) -> PathBuf {
+        self.cache_dir
+            .clone()
+            .unwrap_or_else(ResultCache::default_dir)
+    }
+
+    fn cache_ttl(&self) -> Option<Duration> {
+        (self.cache_ttl > 0).then(|| Duration::from_secs(self.cache_ttl))
+    }
+
+    /// Lets the user replace a rejected API key mid-run, when there is a terminal to ask on.
+    pub fn with_reauthentication(
+        &self,
+        client: &Client,
+        backend: Arc<dyn SentimentBackend>,
+    ) -> Arc<dyn SentimentBackend> {
+        if !std::io::stdin().is_terminal() {
+            return backend;
+        }
+        Arc::new(ReauthenticatingBackend::new(
+            backend,
+            client.clone(),
+            &self.hub_url,
+            &self.key_file,
+        ))
+    }
+
+    /// Puts the result cache in front of `backend`, unless `--no-cache` was
+    /// given or the backend is not worth caching.
+    ///
+    /// A cache that cannot be opened is reported and skipped, it never stops an analysis.
+    pub fn with_cache(
+        &self,
+        backend_kind: BackendKind,
+        backend: Arc<dyn SentimentBackend>,
+    ) -> Arc<dyn SentimentBackend> {
+        if self.no_cache || !backend_kind.is_worth_caching() {
+            return backend;
+        }
+        match ResultCache::open(
+            &self.cache_dir(),
+            self.cache_ttl(),
+            self.cache_max_entries as usize,
+        ) {
+            Ok(cache) => Arc::new(CachedBackend::new(backend, cache)),
+            Err(err) => {
+                eprintln!("{}", format!("{err}, results will not be cached").yellow());
+                backend
+            }
+        }
+    }
+
+    /// Splits the texts too long for the model into chunks before they reach
+    /// `backend`, each chunk being cached on its own.
+    pub fn with_chunking(
+        &self,
+        backend_kind: BackendKind,
+        backend: Arc<dyn SentimentBackend>,
+    ) -> Arc<dyn SentimentBackend> {
+        if self.chunk_tokens == 0 || !backend_kind.has_token_limit() {
+            return backend;
+        }
+        Arc::new(ChunkingBackend::new(
+            backend,
+            self.chunk_tokens as usize,
+            self.chunk_aggregation,
+        ))
+    }
+
+    pub fn pipeline(&self, backend: Arc<dyn SentimentBackend>) -> Pipeline {
+        let delivery = if self.unordered {
+            Delivery::Unordered
+        } else {
+            Delivery::Ordered
+        };
+        Pipeline::new(
+            backend,
+            self.concurrency as usize,
+            self.batch_size as usize,
+            delivery,
+        )
+    }
+}
+
+/// Runs a subcommand without any interactive menu.
+///
+/// Returns the exit code of the process.
+pub fn run_command(command: Command, cli: &Cli, client: &Client) -> i32 {
+    let renderer = Renderer::new(cli.no_color);
+    let backend_kind = cli.backend.unwrap_or(BackendKind::HuggingFace);
+    if let Command::Key { action } = command {
+        return run_key_action(action, client, cli, renderer);
+    }
+    if let Command::Config = command {
+        return show_config(cli);
+    }
+    if let Command::Cache { action } = command {
+        return run_cache_action(action, cli);
+    }
+    if let Command::Info = command {
+        // Describing the backend does not require a key.
+        let backend = match backend_kind.build(client, &cli.backend_settings(String::new())) {
+            Ok(backend) => backend,
+            Err(err) => {
+                eprintln!("{}", err.red());
+                return 1;
+            }
+        };
+        println!("{}", backend.describe());
+        println!("Labels: {}", backend.labels().join(", "));
+        return 0;
+    }
+
+    let backend = match build_backend(cli, client, backend_kind) {
+        Ok(backend) => backend,
+        Err(exit_code) => return exit_code,
+    };
+
+    if let Command::AnalyzeFile {
+        path,
+        input_format,
+        column,
+        pointer,
+        resume,
+        checkpoint_every,
+    } = command
+    {
+        let exit_code = analyze_file(
+            cli,
+            backend.clone(),
+            &path,
+            input_format.unwrap_or_else(|| InputFormat::detect(&path)),
+            &TextField { column, pointer },
+            resume,
+            checkpoint_every as usize,
+        );
+        finish_run(backend.as_ref());
+        return exit_code;
+    }
+
+    let mut output =
+        match ResultWriter::open(cli.format, renderer, backend.model(), cli.output.as_deref()) {
+            Ok(output) => output,
+            Err(err) => {
+                eprintln!("{}", err.red());
+                return 1;
+            }
+        };
+
+    let exit_code = match command {
+        Command::Analyze {
+            text: Some(text), ..
+        } => {
+            let started = Instant::now();
+            let analyzed = {
+                let _spinner = (!cli.no_progress).then(|| Spinner::start("Analyzing..."));
+                backend.analyze(&text)
+            };
+            match analyzed {
+                Ok(sentiment_report) => {
+                    write_result(
+                        &mut output,
+                        &text,
+                        &sentiment_report,
+                        started.elapsed(),
+                        None,
+                    );
+                    0
+                }
+                Err(err) => {
+                    eprintln!("{}", err.to_string().red());
+                    1
+                }
+            }
+        }
+        Command::Analyze {
+            file: Some(path), ..
+        } => {
+            let lines = match File::open(&path) {
+                Ok(file) => BufReader::new(file)
+                    .lines()
+                    .map_while(Result::ok)
+                    .map(|line| line.trim().to_string())
+                    .filter(|line| !line.is_empty()),
+                Err(err) => {
+                    eprintln!(
+                        "{}",
+                        format!("Could not read {}: {err}", path.display()).red()
+                    );
+                    return 1;
+                }
+            };
+
+            let show_progress = progress::visible(cli.no_progress);
+            // Counting the lines first is cheap next to analyzing them.
+            let total = show_progress
+                .then(|| File::open(&path).ok())
+                .flatten()
+                .map(|file| {
+                    BufReader::new(file)
+                        .lines()
+                        .map_while(Result::ok)
+                        .filter(|line| !line.trim().is_empty())
+                        .count() as u64
+                });
+            let mut progress = Progress::new(total, backend.as_ref(), show_progress);
+            output.print_above(progress.bar());
+            let mut exit_code = 0;
+            cli.pipeline(backend.clone())
+                .run(lines, |_, text, result, latency| match result {
+                    Ok(sentiment_report) => {
+                        write_result(&mut output, &text, &sentiment_report, latency, None);
+                        progress.success();
+                    }
+                    Err(err) => {
+                        progress.failure(Some(&err.to_string().red()));
+                        exit_code = 1;
+                    }
+                });
+            progress.finish();
+            exit_code
+        }
+        Command::Analyze { .. } => unreachable!("clap requires either a text or a file"),
+        Command::Feed {
+            sources,
+            interval,
+            once,
+        } => {
+            let sources = if sources.is_empty() {
+                cli.feeds.clone()
+            } else {
+                sources
+            };
+            if sources.is_empty() {
+                eprintln!(
+                    "{}",
+                    "No feed was given and the configuration has none.".red()
+                );
+                return 2;
+            }
+            let show_progress = progress::visible(cli.no_progress);
+            follow_feeds(
+                client,
+                &cli.pipeline(backend.clone()),
+                &mut output,
+                sources,
+                Duration::from_secs(interval.unwrap_or(cli.poll_interval)),
+                once,
+                show_progress,
+            );
+            0
+        }
+        Command::Key { .. }
+        | Command::Cache { .. }
+        | Command::Config
+        | Command::Info
+        | Command::AnalyzeFile { .. } => {
+            unreachable!("handled above")
+        }
+    };
+    if let Err(err) = output.finish() {
+        eprintln!("{}", format!("Could not write the results: {err}").red());
+        return 1;
+    }
+    finish_run(backend.as_ref());
+    exit_code
+}
+
+/// Polls `sources` every `poll_interval` and prints the analysis of each new item.
+///
+/// Returns after the first poll if `once` is set, otherwise once Ctrl-C was
+/// pressed (after the poll in progress), so that the caller can complete the
+/// output and report the key usage.
+/// The analysis of each poll gets its own progress bar with `show_progress`.
+pub fn follow_feeds(
+    client: &Client,
+    pipeline: &Pipeline,
+    output: &mut ResultWriter,
+    sources: Vec<String>,
+    poll_interval: Duration,
+    once: bool,
+    show_progress: bool,
+) {
+    let mut watcher = FeedWatcher::new(sources);
+    let interrupted = if once {
+        Arc::new(AtomicBool::new(false))
+    } else {
+        catch_interruption()
+    };
+    loop {
+        let (items, errors) = watcher.poll(client);
+        for error in errors {
+            eprintln!("{}", error.red());
+        }
+        let texts = items
+            .iter()
+            .map(|item| item.text.clone())
+            .collect::<Vec<_>>();
+        let mut progress = Progress::new(
+            Some(texts.len() as u64),
+            pipeline.backend(),
+            show_progress && !texts.is_empty(),
+        );
+        output.print_above(progress.bar());
+        pipeline.run(
+            texts.into_iter(),
+            |index, text, result, latency| match result {
+                Ok(sentiment_report) => {
+                    write_result(
+                        output,
+                        &text,
+                        &sentiment_report,
+                        latency,
+                        Some(&items[index].source),
+                    );
+                    progress.success();
+                }
+                Err(err) => progress.failure(Some(&err.to_string().red())),
+            },
+        );
+        progress.finish();
+        if once {
+            return;
+        }
+        let next_poll = Instant::now() + poll_interval;
+        while !interrupted.load(Ordering::Relaxed) {
+            let left = next_poll.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            thread::sleep(left.min(Duration::from_millis(200)));
+        }
+        if interrupted.load(Ordering::Relaxed) {
+            eprintln!(
+                "{}",
+                "Received termination signal. Program will now gracefully terminate.".yellow()
+            );
+            return;
+        }
+    }
+}
+
+/// Makes Ctrl-C raise the returned flag instead of terminating the program.
+///
+/// A second Ctrl-C still terminates it at once, should the poll in progress
+/// take too long.
+fn catch_interruption() -> Arc<AtomicBool> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&interrupted);
+    match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => {
+            thread::spawn(move || {
+                runtime.block_on(async {
+                    if tokio::signal::ctrl_c().await.is_ok() {
+                        flag.store(true, Ordering::Relaxed);
+                        if tokio::signal::ctrl_c().await.is_ok() {
+                            std::process::exit(130);
+                        }
+                    }
+                })
+            });
+        }
+        // Ctrl-C keeps terminating the program right away.
+        Err(err) => eprintln!("Could not catch Ctrl-C: {err}"),
+    }
+    interrupted
+}
+
+/// Writes one result, terminating the program if the output is gone.
+///
+/// A closed pipe (`| head`) ends the program quietly.
+pub fn write_result(
+    output: &mut ResultWriter,
+    text: &str,
+    report: &SentimentReport,
+    latency: Duration,
+    source: Option<&str>,
+) {
+    match output.write(text, report, latency, source) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => std::process::exit(0),
+        Err(err) => {
+            eprintln!("{}", format!("Could not write the results: {err}").red());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Ends a run on `backend`: saves what it accumulated (cache statistics) and
+/// prints how each API key of the pool was used, when there are several.
+pub fn finish_run(backend: &dyn SentimentBackend) {
+    backend.finish();
+    if let Some(report) = backend.usage_report() {
+        eprintln!("API key usage:\n{report}");
+    }
+}
+
+/// Enriches `path` into `--output` (or its default), see `Command::AnalyzeFile`.
+fn analyze_file(
+    cli: &Cli,
+    backend: Arc<dyn SentimentBackend>,
+    path: &Path,
+    format: InputFormat,
+    field: &TextField,
+    resume: bool,
+    checkpoint_every: usize,
+) -> i32 {
+    let dataset = match Dataset::read(path, format, field) {
+        Ok(dataset) => dataset,
+        Err(err) => {
+            eprintln!("{}", err.red());
+            return 2;
+        }
+    };
+    let output = cli
+        .output
+        .clone()
+        .unwrap_or_else(|| dataset.default_output_path(path));
+    let job = dataset.job(backend.model());
+    let checkpoint_path = Checkpoint::path_for(&output);
+    let resumed = if resume {
+        match Checkpoint::load(&checkpoint_path) {
+            Ok(Some(checkpoint)) => {
+                if let Err(err) = checkpoint.check(&job) {
+                    eprintln!("{}", err.red());
+                    return 2;
+                }
+                eprintln!(
+                    "Resuming after row {} of {}, {} failed rows to analyze again",
+                    checkpoint.rows_written,
+                    dataset.len(),
+                    checkpoint.failed.len()
+                );
+                Some(checkpoint)
+            }
+            Ok(None) => {
+                eprintln!(
+                    "{}",
+                    format!(
+                        "No checkpoint to resume ({} does not exist)",
+                        checkpoint_path.display()
+                    )
+                    .red()
+                );
+                return 2;
+            }
+            Err(err) => {
+                eprintln!("{}", err.red());
+                return 2;
+            }
+        }
+    } else {
+        if checkpoint_path.exists() {
+            eprintln!(
+                "{}",
+                format!(
+                    "Starting over, {} is replaced (use --resume to continue it instead)",
+                    checkpoint_path.display()
+                )
+                .yellow()
+            );
+        }
+        None
+    };
+    let mut progress = Progress::new(None, backend.as_ref(), progress::visible(cli.no_progress));
+    match dataset.enrich(
+        &cli.pipeline(backend.clone()),
+        job,
+        &output,
+        resumed,
+        checkpoint_every,
+        &mut progress,
+    ) {
+        Ok(0) => {
+            eprintln!("{} rows written to {}", dataset.len(), output.display());
+            0
+        }
+        Ok(failures) => {
+            eprintln!(
+                "{}",
+                format!(
+                    "{} rows written to {}, {failures} of them could not be analyzed",
+                    dataset.len(),
+                    output.display()
+                )
+                .yellow()
+            );
+            1
+        }
+        Err(err) => {
+            eprintln!("{}", err.red());
+            1
+        }
+    }
+}
+
+/// The backend of a run without menus: the key is looked up in the configured
+/// source (prompting only with `--key-source prompt`) and the backend is
+/// wrapped with reauthentication, the cache and chunking.
+///
+/// Errors are reported here, the exit code is returned.
+pub fn build_backend(
+    cli: &Cli,
+    client: &Client,
+    backend_kind: BackendKind,
+) -> Result<Arc<dyn SentimentBackend>, i32> {
+    let huggingface_api_key = match cli.key_source {
+        _ if !backend_kind.needs_api_key() || cli.key_pool.is_some() => String::new(),
+        KeySource::Prompt => match prompt_user_for_api_key(client, &cli.hub_url) {
+            Ok(api_key) => api_key,
+            Err(err) => {
+                eprintln!("{}", err.to_string().red());
+                return Err(validation_exit_code(&err));
+            }
+        },
+        source => match resolve_api_key(source, &cli.key_file) {
+            Some((api_key, _)) => api_key,
+            None => {
+                eprintln!(
+                    "{}",
+                    format!(
+                        "No API key found. Use `key set`, the {} environment variable or `huggingface-cli login`.",
+                        API_KEY_ENV_VARS[0]
+                    )
+                    .red()
+                );
+                return Err(1);
+            }
+        },
+    };
+
+    match backend_kind.build(client, &cli.backend_settings(huggingface_api_key)) {
+        Ok(backend) => Ok(cli.with_chunking(
+            backend_kind,
+            cli.with_cache(
+                backend_kind,
+                cli.with_reauthentication(client, Arc::from(backend)),
+            ),
+        )),
+        Err(err) => {
+            eprintln!("{}", err.red());
+            Err(1)
+        }
+    }
+}
+
+fn run_key_action(action: KeyAction, client: &Client, cli: &Cli, renderer: Renderer) -> i32 {
+    match action {
+        KeyAction::Set { key: Some(api_key) } => {
+            match validate_api_key(client, &cli.hub_url, api_key.trim()) {
+                Ok(token_info) => {
+                    println!("{}", renderer.success(&format!("API key {token_info}")));
+                    save_api_key_to_file(&cli.key_file, api_key.trim());
+                    0
+                }
+                Err(err) => {
+                    eprintln!("{}", err.to_string().red());
+                    validation_exit_code(&err)
+                }
+            }
+        }
+        KeyAction::Set { key: None } => match prompt_user_for_api_key(client, &cli.hub_url) {
+            Ok(api_key) => {
+                save_api_key_to_file(&cli.key_file, &api_key);
+                0
+            }
+            Err(err) => {
+                eprintln!("{}", err.to_string().red());
+                validation_exit_code(&err)
+            }
+        },
+        KeyAction::Clear => {
+            let (removed, remaining) = remove_saved_keys(&cli.key_file);
+            for path in &removed {
+                println!("Removed the saved API key at {}", path.display());
+            }
+            for err in &remaining {
+                eprintln!("{}", err.red());
+            }
+            if removed.is_empty() && remaining.is_empty() {
+                println!("No API key was saved");
+            }
+            i32::from(!remaining.is_empty())
+        }
+        KeyAction::Check => {
+            let Some((api_key, origin)) = resolve_api_key(cli.key_source, &cli.key_file) else {
+                eprintln!("{}", "No API key found.".red());
+                return 1;
+            };
+            match validate_api_key(client, &cli.hub_url, &api_key) {
+                Ok(token_info) => {
+                    println!(
+                        "{}",
+                        renderer.success(&format!("API key from {origin} is valid, {token_info}."))
+                    );
+                    println!("Inference permission: yes");
+                    0
+                }
+                Err(err) => {
+                    eprintln!("{}", err.to_string().red());
+                    validation_exit_code(&err)
+                }
+            }
+        }
+        KeyAction::Source => {
+            let env_vars = API_KEY_ENV_VARS.join(", then ");
+            let hf_cli_paths = hf_cli_token_paths()
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" or ");
+            println!("Precedence (--key-source auto):");
+            println!("  1. environment: {env_vars}");
+            println!("  2. saved key: {}", cli.key_file.display());
+            println!("  3. Hugging Face CLI token: {hf_cli_paths}");
+            match locate_api_key(cli.key_source, &cli.key_file) {
+                Some(KeyOrigin::Prompt) => {
+                    println!("Active key: asked for on every run (--key-source prompt)");
+                    0
+                }
+                Some(origin) => {
+                    println!("Active key: from {origin}");
+                    0
+                }
+                None => {
+                    eprintln!(
+                        "{}",
+                        format!(
+                            "No API key found for --key-source {}",
+                            cli.key_source
+                                .to_possible_value()
+                                .map(|value| value.get_name().to_string())
+                                .unwrap_or_default()
+                        )
+                        .red()
+                    );
+                    1
+                }
+            }
+        }
+    }
+}
+
+/// `1` for a key that is bad, `3` when it could not be checked at all.
+fn validation_exit_code(err: &KeyValidationError) -> i32 {
+    match err {
+        KeyValidationError::Rejected | KeyValidationError::MissingPermission(_) => 1,
+        KeyValidationError::Unreachable(_) | KeyValidationError::Unexpected(_) => 3,
+        KeyValidationError::NoPrompt(_) => 2,
+    }
+}
+
+fn show_config(cli: &Cli) -> i32 {
+    let active = match config::locate(cli) {
+        Ok(active) => active,
+        Err(err) => {
+            eprintln!("{}", err.red());
+            return 1;
+        }
+    };
+    match &active.file {
+        Some(file) => {
+            println!("Configuration: {}", active.path.display());
+            let profiles = file.profiles();
+            if profiles.is_empty() {
+                println!("Profiles: none");
+            } else {
+                println!("Profiles: {}", profiles.join(", "));
+            }
+        }
+        None => println!("Configuration: {} (not found)", active.path.display()),
+    }
+    println!(
+        "Active profile: {}",
+        active.profile.as_deref().unwrap_or("none")
+    );
+    0
+}
+
+fn run_cache_action(action: CacheAction, cli: &Cli) -> i32 {
+    let cache_dir = cli.cache_dir();
+    match action {
+        CacheAction::Stats => {
+            let summary = ResultCache::summary(&cache_dir, cli.cache_ttl());
+            let lookups = summary.lifetime.hits + summary.lifetime.misses;
+            println!("Location: {}", summary.dir.display());
+            println!(
+                "Entries: {} ({} expired), {:.1} KiB",
+                summary.entries,
+                summary.expired,
+                summary.size_bytes as f64 / 1024.0
+            );
+            if let Some(oldest) = summary
+                .oldest
+                .and_then(|oldest| chrono::DateTime::from_timestamp(oldest, 0))
+            {
+                println!("Oldest entry: {}", oldest.to_rfc3339());
+            }
+            if lookups > 0 {
+                println!(
+                    "Hits: {} / {} lookups ({:.1}%)",
+                    summary.lifetime.hits,
+                    lookups,
+                    summary.lifetime.hits as f64 * 100.0 / lookups as f64
+                );
+            } else {
+                println!("Hits: no lookups yet");
+            }
+            0
+        }
+        CacheAction::Clear => match ResultCache::clear(&cache_dir) {
+            Ok(_) => {
+                println!("Cleared the result cache");
+                0
+            }
+            Err(err) => {
+                eprintln!("{}", format!("Failed to clear the cache: {err}").red());
+                1
+            }
+        },
+    }
+}
+
+static DEFAULT_MODEL_ID: &str = "cardiffnlp/twitter-roberta-base-sentiment-latest";
+const DEFAULT_POLL_INTERVAL_SECS: u64 = 300;
+const DEFAULT_BATCH_SIZE: u32 = 16;
+const DEFAULT_CONCURRENCY: u32 = 4;
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_KEY_COOLDOWN_SECS: u64 = 60;
+const DEFAULT_CACHE_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+const DEFAULT_CACHE_MAX_ENTRIES: u64 = 100_000;
+const DEFAULT_CHECKPOINT_ROWS: u64 = 1_000;
+/// Well under the 512 tokens of RoBERTa-based models, the estimate being rough.
+const DEFAULT_CHUNK_TOKENS: u32 = 400;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, ErrorKind> {
+        Cli::try_parse_from(std::iter::once("sentiment_analyzer").chain(args.iter().copied()))
+            .and_then(|cli| cli.check_conflicts().map(|_| cli))
+            .map_err(|err| err.kind())
+    }
+
+    #[test]
+    fn analyze_takes_either_a_text_or_a_file() {
+        assert!(matches!(
+            parse(&["analyze", "great"]).unwrap().command,
+            Some(Command::Analyze {
+                text: Some(_),
+                file: None,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse(&["analyze", "--file", "posts.txt"]).unwrap().command,
+            Some(Command::Analyze {
+                text: None,
+                file: Some(_),
+                ..
+            })
+        ));
+        assert_eq!(
+            parse(&["analyze", "great", "--file", "posts.txt"]).err(),
+            Some(ErrorKind::ArgumentConflict)
+        );
+        assert_eq!(
+            parse(&["analyze"]).err(),
+            Some(ErrorKind::MissingRequiredArgument)
+        );
+    }
+
+    #[test]
+    fn null_separated_records_require_stdin() {
+        assert_eq!(
+            parse(&["--null"]).err(),
+            Some(ErrorKind::MissingRequiredArgument)
+        );
+        let cli = parse(&["--stdin", "-0"]).unwrap();
+        assert!(cli.stdin && cli.null);
+    }
+
+    #[test]
+    fn stdin_is_not_combined_with_a_subcommand() {
+        assert_eq!(
+            parse(&["--stdin", "analyze", "great"]).err(),
+            Some(ErrorKind::ArgumentConflict)
+        );
+        assert_eq!(
+            parse(&["analyze", "great", "--stdin"]).err(),
+            Some(ErrorKind::UnknownArgument)
+        );
+        assert!(parse(&["--stdin"]).unwrap().command.is_none());
+    }
+}
@@ -0,0 +1,365 @@
+use crate::backend::{ApiKeyList, BackendKind, KeyRotation};
+use crate::chunking::ChunkAggregation;
+use crate::cli::{Cli, KeySource};
+use crate::labels::LabelMapping;
+use crate::output::OutputFormat;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, ValueEnum};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Contents of the configuration file.
+///
+/// Settings at the top level apply to every run, those of the active profile
+/// override them:
+///
+/// ```toml
+/// default_profile = "work"
+/// format = "json"
+///
+/// [profiles.work]
+/// backend = "huggingface"
+/// model = "ProsusAI/finbert"
+/// feeds = ["https://example.com/markets.rss"]
+///
+/// [profiles.research]
+/// backend = "lexicon"
+/// ```
+///
+/// The file sits below command line flags and environment variables: a
+/// setting is only taken from it when the flag was not given either way.
+#[derive(Debug, Default)]
+pub struct ConfigFile {
+    /// Profile used when neither `--profile` nor `SENTIMENT_PROFILE` is set.
+    default_profile: Option<String>,
+    profiles: BTreeMap<String, Layer>,
+    base: Layer,
+}
+
+/// One level of settings, every one of them optional.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Layer {
+    backend: Option<String>,
+    model: Option<String>,
+    model_dir: Option<PathBuf>,
+    labels: Option<String>,
+    endpoint: Option<String>,
+    hub_url: Option<String>,
+    key_source: Option<String>,
+    key_file: Option<PathBuf>,
+    /// File of API keys used in turn.
+    key_pool: Option<String>,
+    key_rotation: Option<String>,
+    key_cooldown: Option<u64>,
+    /// Seconds before a request to the Inference API is abandoned.
+    timeout: Option<u64>,
+    retries: Option<u32>,
+    wait_for_model: Option<bool>,
+    format: Option<String>,
+    output: Option<PathBuf>,
+    batch_size: Option<u32>,
+    concurrency: Option<u32>,
+    unordered: Option<bool>,
+    chunk_tokens: Option<u32>,
+    chunk_aggregation: Option<String>,
+    /// Feeds followed when none is given on the command line.
+    feeds: Option<Vec<String>>,
+    /// Seconds between two polls of the feeds.
+    interval: Option<u64>,
+    no_cache: Option<bool>,
+    cache_ttl: Option<u64>,
+    cache_max_entries: Option<u64>,
+    cache_dir: Option<PathBuf>,
+    no_color: Option<bool>,
+    no_progress: Option<bool>,
+}
+
+impl Layer {
+    /// Settings of `self`, overridden by those set in `other`.
+    fn overridden_by(self, other: Layer) -> Layer {
+        Layer {
+            backend: other.backend.or(self.backend),
+            model: other.model.or(self.model),
+            model_dir: other.model_dir.or(self.model_dir),
+            labels: other.labels.or(self.labels),
+            endpoint: other.endpoint.or(self.endpoint),
+            hub_url: other.hub_url.or(self.hub_url),
+            key_source: other.key_source.or(self.key_source),
+            key_file: other.key_file.or(self.key_file),
+            key_pool: other.key_pool.or(self.key_pool),
+            key_rotation: other.key_rotation.or(self.key_rotation),
+            key_cooldown: other.key_cooldown.or(self.key_cooldown),
+            timeout: other.timeout.or(self.timeout),
+            retries: other.retries.or(self.retries),
+            wait_for_model: other.wait_for_model.or(self.wait_for_model),
+            format: other.format.or(self.format),
+            output: other.output.or(self.output),
+            batch_size: other.batch_size.or(self.batch_size),
+            concurrency: other.concurrency.or(self.concurrency),
+            unordered: other.unordered.or(self.unordered),
+            chunk_tokens: other.chunk_tokens.or(self.chunk_tokens),
+            chunk_aggregation: other.chunk_aggregation.or(self.chunk_aggregation),
+            feeds: other.feeds.or(self.feeds),
+            interval: other.interval.or(self.interval),
+            no_cache: other.no_cache.or(self.no_cache),
+            cache_ttl: other.cache_ttl.or(self.cache_ttl),
+            cache_max_entries: other.cache_max_entries.or(self.cache_max_entries),
+            cache_dir: other.cache_dir.or(self.cache_dir),
+            no_color: other.no_color.or(self.no_color),
+            no_progress: other.no_progress.or(self.no_progress),
+        }
+    }
+}
+
+impl ConfigFile {
+    /// `config.toml` in the per-user configuration directory.
+    pub fn default_path() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("sentiment_analyzer")
+            .join("config.toml")
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let raw = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+        Self::parse(&raw).map_err(|e| format!("Invalid configuration {}: {e}", path.display()))
+    }
+
+    fn parse(raw: &str) -> Result<Self, toml::de::Error> {
+        let mut table: toml::Table = toml::from_str(raw)?;
+        // The top level is split by hand: `deny_unknown_fields` does nothing on
+        // a flattened layer, so a misspelt setting would be silently ignored.
+        let default_profile = table
+            .remove("default_profile")
+            .map(toml::Value::try_into)
+            .transpose()?;
+        let profiles = table
+            .remove("profiles")
+            .map(toml::Value::try_into)
+            .transpose()?
+            .unwrap_or_default();
+        let base = toml::Value::Table(table).try_into()?;
+        Ok(ConfigFile {
+            default_profile,
+            profiles,
+            base,
+        })
+    }
+
+    pub fn profiles(&self) -> Vec<&str> {
+        self.profiles.keys().map(String::as_str).collect()
+    }
+
+    /// Top level settings merged with those of `profile`.
+    fn layer(&self, profile: Option<&str>) -> Result<Layer, String> {
+        match profile {
+            None => Ok(self.base.clone()),
+            Some(profile) => match self.profiles.get(profile) {
+                Some(layer) => Ok(self.base.clone().overridden_by(layer.clone())),
+                None => Err(format!(
+                    "Unknown profile '{profile}' (available: {})",
+                    if self.profiles.is_empty() {
+                        "none".to_string()
+                    } else {
+                        self.profiles().join(", ")
+                    }
+                )),
+            },
+        }
+    }
+}
+
+/// Where the configuration comes from, shown by the `config` subcommand.
+pub struct ActiveConfig {
+    pub path: PathBuf,
+    pub file: Option<ConfigFile>,
+    pub profile: Option<String>,
+}
+
+/// Locates the configuration file and the profile to use.
+///
+/// A missing file is only an error when it was asked for explicitly.
+pub fn locate(cli: &Cli) -> Result<ActiveConfig, String> {
+    let path = cli.config.clone().unwrap_or_else(ConfigFile::default_path);
+    let file = if path.exists() || cli.config.is_some() {
+        Some(ConfigFile::load(&path)?)
+    } else {
+        None
+    };
+    let profile = cli
+        .profile
+        .clone()
+        .or_else(|| file.as_ref().and_then(|file| file.default_profile.clone()));
+    if profile.is_some() && file.is_none() {
+        return Err(format!(
+            "Profile '{}' was requested but there is no configuration file at {}",
+            profile.unwrap_or_default(),
+            path.display()
+        ));
+    }
+    Ok(ActiveConfig {
+        path,
+        file,
+        profile,
+    })
+}
+
+/// Fills every setting of `cli` that was neither given as a flag nor through
+/// its environment variable with the one of the configuration file.
+pub fn apply(cli: &mut Cli, matches: &ArgMatches) -> Result<(), String> {
+    let active = locate(cli)?;
+    let Some(file) = &active.file else {
+        return Ok(());
+    };
+    let layer = file.layer(active.profile.as_deref())?;
+    let unset = |id: &str| {
+        matches
+            .value_source(id)
+            .is_none_or(|source| source == ValueSource::DefaultValue)
+    };
+    let invalid = |setting: &str, err: String| {
+        format!("Invalid '{setting}' in {}: {err}", active.path.display())
+    };
+
+    if let (Some(backend), true) = (layer.backend, unset("backend")) {
+        cli.backend =
+            Some(BackendKind::from_str(&backend, true).map_err(|e| invalid("backend", e))?);
+    }
+    if let (Some(model), true) = (layer.model, unset("model")) {
+        cli.model = Some(model);
+    }
+    if let (Some(model_dir), true) = (layer.model_dir, unset("model_dir")) {
+        cli.model_dir = Some(model_dir);
+    }
+    if let (Some(labels), true) = (layer.labels, unset("labels")) {
+        cli.labels = Some(LabelMapping::parse(&labels).map_err(|e| invalid("labels", e))?);
+    }
+    if let (Some(endpoint), true) = (layer.endpoint, unset("endpoint")) {
+        cli.endpoint = endpoint;
+    }
+    if let (Some(hub_url), true) = (layer.hub_url, unset("hub_url")) {
+        cli.hub_url = hub_url;
+    }
+    if let (Some(key_source), true) = (layer.key_source, unset("key_source")) {
+        cli.key_source =
+            KeySource::from_str(&key_source, true).map_err(|e| invalid("key_source", e))?;
+    }
+    if let (Some(key_file), true) = (layer.key_file, unset("key_file")) {
+        cli.key_file = key_file;
+    }
+    if let (Some(key_pool), true) = (layer.key_pool, unset("key_pool")) {
+        cli.key_pool = Some(ApiKeyList::from_file(&key_pool).map_err(|e| invalid("key_pool", e))?);
+    }
+    if let (Some(key_rotation), true) = (layer.key_rotation, unset("key_rotation")) {
+        cli.key_rotation =
+            KeyRotation::from_str(&key_rotation, true).map_err(|e| invalid("key_rotation", e))?;
+    }
+    if let (Some(key_cooldown), true) = (layer.key_cooldown, unset("key_cooldown")) {
+        cli.key_cooldown = key_cooldown;
+    }
+    if let (Some(timeout), true) = (layer.timeout, unset("timeout")) {
+        cli.timeout = timeout;
+    }
+    if let (Some(retries), true) = (layer.retries, unset("retries")) {
+        cli.retries = retries;
+    }
+    if let (Some(wait_for_model), true) = (layer.wait_for_model, unset("wait_for_model")) {
+        cli.wait_for_model = wait_for_model;
+    }
+    if let (Some(format), true) = (layer.format, unset("format")) {
+        cli.format = OutputFormat::from_str(&format, true).map_err(|e| invalid("format", e))?;
+    }
+    if let (Some(output), true) = (layer.output, unset("output")) {
+        cli.output = Some(output);
+    }
+    if let (Some(batch_size), true) = (layer.batch_size, unset("batch_size")) {
+        if batch_size == 0 {
+            return Err(invalid("batch_size", "must be at least 1".to_string()));
+        }
+        cli.batch_size = batch_size;
+    }
+    if let (Some(concurrency), true) = (layer.concurrency, unset("concurrency")) {
+        if concurrency == 0 {
+            return Err(invalid("concurrency", "must be at least 1".to_string()));
+        }
+        cli.concurrency = concurrency;
+    }
+    if let (Some(unordered), true) = (layer.unordered, unset("unordered")) {
+        cli.unordered = unordered;
+    }
+    if let (Some(chunk_tokens), true) = (layer.chunk_tokens, unset("chunk_tokens")) {
+        cli.chunk_tokens = chunk_tokens;
+    }
+    if let (Some(chunk_aggregation), true) = (layer.chunk_aggregation, unset("chunk_aggregation")) {
+        cli.chunk_aggregation = ChunkAggregation::from_str(&chunk_aggregation, true)
+            .map_err(|e| invalid("chunk_aggregation", e))?;
+    }
+    if let Some(feeds) = layer.feeds {
+        cli.feeds = feeds;
+    }
+    if let Some(interval) = layer.interval {
+        cli.poll_interval = interval;
+    }
+    if let (Some(no_cache), true) = (layer.no_cache, unset("no_cache")) {
+        cli.no_cache = no_cache;
+    }
+    if let (Some(cache_ttl), true) = (layer.cache_ttl, unset("cache_ttl")) {
+        cli.cache_ttl = cache_ttl;
+    }
+    if let (Some(cache_max_entries), true) = (layer.cache_max_entries, unset("cache_max_entries")) {
+        if cache_max_entries == 0 {
+            return Err(invalid(
+                "cache_max_entries",
+                "must be at least 1".to_string(),
+            ));
+        }
+        cli.cache_max_entries = cache_max_entries;
+    }
+    if let (Some(cache_dir), true) = (layer.cache_dir, unset("cache_dir")) {
+        cli.cache_dir = Some(cache_dir);
+    }
+    if let (Some(no_color), true) = (layer.no_color, unset("no_color")) {
+        cli.no_color = no_color;
+    }
+    if let (Some(no_progress), true) = (layer.no_progress, unset("no_progress")) {
+        cli.no_progress = no_progress;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_override_the_top_level() {
+        let file = ConfigFile::parse(
+            r#"
+            default_profile = "work"
+            format = "json"
+            model = "base"
+
+            [profiles.work]
+            model = "ProsusAI/finbert"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(file.default_profile.as_deref(), Some("work"));
+        let layer = file.layer(Some("work")).unwrap();
+        assert_eq!(layer.model.as_deref(), Some("ProsusAI/finbert"));
+        assert_eq!(layer.format.as_deref(), Some("json"));
+        assert!(file.layer(Some("home")).is_err());
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected_at_every_level() {
+        let top = ConfigFile::parse("fromat = \"json\"").unwrap_err();
+        assert!(top.to_string().contains("fromat"), "{top}");
+        let profile = ConfigFile::parse("[profiles.work]\nfromat = \"json\"").unwrap_err();
+        assert!(profile.to_string().contains("fromat"), "{profile}");
+    }
+}
@@ -0,0 +1,1068 @@
+use crate::backend::{BackendError, SentimentBackend};
+use crate::cli::KeySource;
+use crate::keystore::{self, EncryptedKey, StoredKey};
+use crate::render::Tint;
+use crate::report::SentimentReport;
+use inquire::InquireError::{OperationCanceled, OperationInterrupted};
+use inquire::{Confirm, Password, PasswordDisplayMode, Text};
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Environment variables holding a HuggingFace token, in order of precedence
+/// (the same order as the `huggingface_hub` library).
+pub static API_KEY_ENV_VARS: &[&str] = &["HF_TOKEN", "HUGGING_FACE_HUB_TOKEN"];
+
+/// Where earlier versions saved the key in plaintext, migrated when found.
+static API_KEY_SAVE_PATH: &str = "./saved_key.txt";
+/// Passphrase of the saved key, for runs that cannot prompt.
+static PASSPHRASE_ENV_VAR: &str = "SENTIMENT_KEY_PASSPHRASE";
+const PASSPHRASE_ATTEMPTS: u32 = 3;
+
+/// Where the API key in use was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyOrigin {
+    EnvVar(&'static str),
+    /// The encrypted key saved by `key set`.
+    SavedKey(PathBuf),
+    /// The token written by `huggingface-cli login`.
+    HfCli(PathBuf),
+    Prompt,
+}
+
+impl fmt::Display for KeyOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyOrigin::EnvVar(var) => write!(f, "the {var} environment variable"),
+            KeyOrigin::SavedKey(path) => write!(f, "the saved key at {}", path.display()),
+            KeyOrigin::HfCli(path) => {
+                write!(f, "the Hugging Face CLI token at {}", path.display())
+            }
+            KeyOrigin::Prompt => write!(f, "the prompt"),
+        }
+    }
+}
+
+/// Looks the API key up as configured by `source`, without asking for the key
+/// itself.
+///
+/// It is not free of prompts though: unlocking the saved key asks for its
+/// passphrase (unless `SENTIMENT_KEY_PASSPHRASE` is set), and a plaintext key
+/// of earlier versions is offered to be encrypted. Without a terminal both
+/// prompts fail and the saved key is skipped, or used unencrypted.
+///
+/// In `auto` mode the sources are tried in this order, the first one holding
+/// a key wins:
+///
+/// 1. the `HF_TOKEN` then `HUGGING_FACE_HUB_TOKEN` environment variables,
+/// 2. the key saved with `key set` (unlocked with its passphrase),
+/// 3. the token file of the Hugging Face CLI.
+pub fn resolve_api_key(source: KeySource, key_file: &Path) -> Option<(String, KeyOrigin)> {
+    let from_saved_key = || {
+        let (api_key_was_saved, api_key) = check_for_api_key_file(key_file);
+        api_key_was_saved.then(|| (api_key, KeyOrigin::SavedKey(key_file.to_path_buf())))
+    };
+    match source {
+        KeySource::Auto => from_env().or_else(from_saved_key).or_else(from_hf_cli),
+        KeySource::File => from_saved_key(),
+        KeySource::Env => from_env(),
+        KeySource::HfCli => from_hf_cli(),
+        KeySource::Prompt => None,
+    }
+}
+
+/// The source `resolve_api_key` would pick, found without unlocking the saved key.
+pub fn locate_api_key(source: KeySource, key_file: &Path) -> Option<KeyOrigin> {
+    let saved_key = || {
+        let saved = match keystore::read(key_file) {
+            Ok(Some(_)) => true,
+            // A plaintext key of earlier versions is still picked up (and migrated).
+            _ => matches!(
+                keystore::read(Path::new(API_KEY_SAVE_PATH)),
+                Ok(Some(StoredKey::Plaintext(_)))
+            ),
+        };
+        saved.then(|| KeyOrigin::SavedKey(key_file.to_path_buf()))
+    };
+    let env = || from_env().map(|(_, origin)| origin);
+    let hf_cli = || from_hf_cli().map(|(_, origin)| origin);
+    match source {
+        KeySource::Auto => env().or_else(saved_key).or_else(hf_cli),
+        KeySource::File => saved_key(),
+        KeySource::Env => env(),
+        KeySource::HfCli => hf_cli(),
+        KeySource::Prompt => Some(KeyOrigin::Prompt),
+    }
+}
+
+fn from_env() -> Option<(String, KeyOrigin)> {
+    API_KEY_ENV_VARS.iter().find_map(|var| {
+        std::env::var(var)
+            .ok()
+            .map(|api_key| api_key.trim().to_string())
+            .filter(|api_key| !api_key.is_empty())
+            .map(|api_key| (api_key, KeyOrigin::EnvVar(var)))
+    })
+}
+
+fn from_hf_cli() -> Option<(String, KeyOrigin)> {
+    hf_cli_token_paths().into_iter().find_map(|path| {
+        std::fs::read_to_string(&path)
+            .ok()
+            .map(|api_key| api_key.trim().to_string())
+            .filter(|api_key| !api_key.is_empty())
+            .map(|api_key| (api_key, KeyOrigin::HfCli(path)))
+    })
+}
+
+/// Token files of the Hugging Face CLI, most specific first.
+///
+/// `HF_TOKEN_PATH` overrides the location, otherwise the token sits in
+/// `$HF_HOME/token` (`HF_HOME` defaulting to `$XDG_CACHE_HOME/huggingface`,
+/// i.e. `~/.cache/huggingface`). Old CLI versions used `~/.huggingface/token`.
+/// Variables set to an empty value count as unset, as they do for the CLI.
+pub fn hf_cli_token_paths() -> Vec<PathBuf> {
+    if let Some(path) = non_empty_var("HF_TOKEN_PATH") {
+        return vec![PathBuf::from(path)];
+    }
+    let hf_home = non_empty_var("HF_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            non_empty_var("XDG_CACHE_HOME").map(|cache| PathBuf::from(cache).join("huggingface"))
+        })
+        .or_else(|| dirs::home_dir().map(|home| home.join(".cache").join("huggingface")));
+    hf_home
+        .map(|hf_home| hf_home.join("token"))
+        .into_iter()
+        .chain(dirs::home_dir().map(|home| home.join(".huggingface").join("token")))
+        .collect()
+}
+
+fn non_empty_var(name: &str) -> Option<OsString> {
+    std::env::var_os(name).filter(|value| !value.is_empty())
+}
+
+/// Default of `--hub-url`, where tokens are validated.
+pub static HF_HUB_URL: &str = "https://huggingface.co";
+
+/// Fine-grained permissions allowing calls to the Inference API.
+static INFERENCE_PERMISSIONS: &[&str] = &[
+    "inference.serverless.write",
+    "inference.endpoints.infer.write",
+];
+
+/// What the hub knows about a token.
+#[derive(Debug, Clone)]
+pub struct TokenInfo {
+    /// User (or organization) owning the token.
+    pub owner: String,
+    pub token_name: Option<String>,
+    /// `read`, `write` or `fineGrained`.
+    pub role: Option<String>,
+    /// Whether the token may call the Inference API.
+    pub can_infer: bool,
+}
+
+impl fmt::Display for TokenInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "owned by {}", self.owner)?;
+        if let Some(token_name) = &self.token_name {
+            write!(f, ", token '{token_name}'")?;
+        }
+        if let Some(role) = &self.role {
+            write!(f, " ({role})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a key could not be validated, telling a bad key apart from a hub we could not ask.
+#[derive(Debug, Clone)]
+pub enum KeyValidationError {
+    /// The hub does not know the key (401).
+    Rejected,
+    /// The key is valid but may not call the Inference API.
+    MissingPermission(TokenInfo),
+    /// The hub could not be reached, or failed on its side.
+    Unreachable(String),
+    /// The hub answered something we do not understand.
+    Unexpected(String),
+    /// No key could be asked for, e.g. without a terminal.
+    NoPrompt(String),
+}
+
+impl fmt::Display for KeyValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyValidationError::Rejected => write!(f, "The API key was rejected by Hugging Face"),
+            KeyValidationError::MissingPermission(token_info) => write!(
+                f,
+                "The API key ({token_info}) lacks the permission to call the Inference API"
+            ),
+            KeyValidationError::Unreachable(message) => {
+                write!(
+                    f,
+                    "Could not reach Hugging Face to validate the API key: {message}"
+                )
+            }
+            KeyValidationError::Unexpected(message) => {
+                write!(
+                    f,
+                    "Unexpected answer while validating the API key: {message}"
+                )
+            }
+            KeyValidationError::NoPrompt(message) => {
+                write!(f, "Could not ask for the API key: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for KeyValidationError {}
+
+#[derive(Deserialize)]
+struct WhoAmI {
+    name: String,
+    auth: Option<WhoAmIAuth>,
+}
+
+#[derive(Deserialize)]
+struct WhoAmIAuth {
+    #[serde(rename = "accessToken")]
+    access_token: Option<AccessToken>,
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    role: Option<String>,
+    #[serde(rename = "fineGrained")]
+    fine_grained: Option<FineGrained>,
+}
+
+#[derive(Deserialize)]
+struct FineGrained {
+    #[serde(default)]
+    global: Vec<String>,
+}
+
+/// Checks that `api_key` is known to the hub and allowed to run inferences.
+pub fn validate_api_key(
+    client: &Client,
+    hub_url: &str,
+    api_key: &str,
+) -> Result<TokenInfo, KeyValidationError> {
+    let token_info = whoami(client, hub_url, api_key)?;
+    if token_info.can_infer {
+        Ok(token_info)
+    } else {
+        Err(KeyValidationError::MissingPermission(token_info))
+    }
+}
+
+/// Asks the hub's whoami endpoint about `api_key`.
+///
+/// Unlike a test inference this costs no quota and does not depend on the
+/// model being loaded.
+pub fn whoami(
+    client: &Client,
+    hub_url: &str,
+    api_key: &str,
+) -> Result<TokenInfo, KeyValidationError> {
+    let response = client
+        .get(format!("{}/api/whoami-v2", hub_url.trim_end_matches('/')))
+        .bearer_auth(api_key.trim())
+        .send()
+        .map_err(|e| KeyValidationError::Unreachable(e.to_string()))?;
+    let status = response.status();
+    if status == StatusCode::UNAUTHORIZED {
+        return Err(KeyValidationError::Rejected);
+    }
+    if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        return Err(KeyValidationError::Unreachable(format!("HTTP {status}")));
+    }
+    if !status.is_success() {
+        return Err(KeyValidationError::Unexpected(format!("HTTP {status}")));
+    }
+
+    let whoami = response
+        .json::<WhoAmI>()
+        .map_err(|e| KeyValidationError::Unexpected(e.to_string()))?;
+    let access_token = whoami.auth.and_then(|auth| auth.access_token);
+    let (token_name, role, can_infer) = match access_token {
+        Some(token) => {
+            let can_infer = match (token.role.as_deref(), &token.fine_grained) {
+                (Some("fineGrained"), Some(fine_grained)) => fine_grained
+                    .global
+                    .iter()
+                    .any(|permission| INFERENCE_PERMISSIONS.contains(&permission.as_str())),
+                (Some("fineGrained"), None) => false,
+                // Classic read and write tokens may all call the Inference API.
+                _ => true,
+            };
+            (token.display_name, token.role, can_infer)
+        }
+        // Not an access token (e.g. OAuth), the hub does not tell us more.
+        None => (None, None, true),
+    };
+    Ok(TokenInfo {
+        owner: whoami.name,
+        token_name,
+        role,
+        can_infer,
+    })
+}
+
+/// Reads the saved API key, asking for the passphrase that unlocks it.
+///
+/// A plaintext key left by earlier versions (in `key_file` itself or in
+/// `./saved_key.txt`) is still used, and the user is offered to encrypt it
+/// into `key_file`.
+fn check_for_api_key_file(key_file: &Path) -> (bool, String) {
+    let stored_key = match keystore::read(key_file) {
+        Ok(Some(stored_key)) => Some((stored_key, key_file)),
+        Ok(None) => match keystore::read(Path::new(API_KEY_SAVE_PATH)) {
+            Ok(Some(StoredKey::Plaintext(api_key))) => {
+                Some((StoredKey::Plaintext(api_key), Path::new(API_KEY_SAVE_PATH)))
+            }
+            _ => None,
+        },
+        Err(err) => {
+            eprintln!("{}", err.red());
+            None
+        }
+    };
+
+    match stored_key {
+        None => (false, String::new()),
+        Some((StoredKey::Encrypted(encrypted_key), _)) => {
+            for _ in 0..PASSPHRASE_ATTEMPTS {
+                let Some(passphrase) = ask_passphrase("Passphrase of the saved API key: ", false)
+                else {
+                    break;
+                };
+                match encrypted_key.open(&passphrase) {
+                    Ok(api_key) => return (true, api_key),
+                    Err(err) => eprintln!("{}", err.red()),
+                }
+                if std::env::var_os(PASSPHRASE_ENV_VAR).is_some() {
+                    // Asking again would only get the same passphrase.
+                    break;
+                }
+            }
+            eprintln!("{}", "Could not unlock the saved API key.".red());
+            (false, String::new())
+        }
+        Some((StoredKey::Plaintext(api_key), found_at)) => {
+            migrate_plaintext_key(key_file, found_at, &api_key);
+            (true, api_key)
+        }
+    }
+}
+
+/// Offers to encrypt a plaintext key into `key_file`, then removes the plaintext copy.
+fn migrate_plaintext_key(key_file: &Path, found_at: &Path, api_key: &str) {
+    eprintln!(
+        "{}",
+        format!(
+            "The API key saved at {} is not encrypted.",
+            found_at.display()
+        )
+        .yellow()
+    );
+    let should_migrate = std::env::var_os(PASSPHRASE_ENV_VAR).is_some()
+        || Confirm::new("Encrypt it with a passphrase now?")
+            .with_default(true)
+            .prompt()
+            .unwrap_or(false);
+    if !should_migrate {
+        return;
+    }
+    if write_encrypted_api_key(key_file, api_key) && found_at != key_file {
+        match fs::remove_file(found_at) {
+            Ok(_) => println!("Removed the plaintext copy at {}", found_at.display()),
+            Err(err) => eprintln!(
+                "{}",
+                format!(
+                    "Could not remove the plaintext copy at {}: {err}",
+                    found_at.display()
+                )
+                .red()
+            ),
+        }
+    }
+}
+
+/// The passphrase from `SENTIMENT_KEY_PASSPHRASE`, or typed in by the user.
+///
+/// Returns `None` when the user gave up.
+fn ask_passphrase(message: &str, new_passphrase: bool) -> Option<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV_VAR) {
+        return Some(passphrase);
+    }
+    let prompt = Password::new(message).with_display_mode(PasswordDisplayMode::Hidden);
+    let prompt = if new_passphrase {
+        prompt.with_custom_confirmation_message("Confirm the passphrase: ")
+    } else {
+        prompt.without_confirmation()
+    };
+    match prompt.prompt() {
+        Ok(passphrase) if new_passphrase && passphrase.is_empty() => {
+            eprintln!("{}", "The passphrase cannot be empty.".red());
+            None
+        }
+        Ok(passphrase) => Some(passphrase),
+        Err(OperationCanceled | OperationInterrupted) => std::process::exit(0),
+        Err(err) => {
+            eprintln!("{}", format!("Could not read the passphrase: {err}").red());
+            None
+        }
+    }
+}
+
+/// Encrypts `api_key` under a new passphrase into `key_file`, returns whether it was saved.
+fn write_encrypted_api_key(key_file: &Path, api_key: &str) -> bool {
+    let Some(passphrase) = ask_passphrase("Passphrase to encrypt the API key with: ", true) else {
+        return false;
+    };
+    match EncryptedKey::seal(api_key, &passphrase)
+        .and_then(|encrypted_key| keystore::write(key_file, &encrypted_key))
+    {
+        Ok(_) => {
+            println!("Saved the encrypted API key to {}", key_file.display());
+            true
+        }
+        Err(err) => {
+            eprintln!("{}", format!("Failed to save API key: {err}").red());
+            false
+        }
+    }
+}
+
+/// Deletes the saved key, and the plaintext one earlier versions left in
+/// `./saved_key.txt`.
+///
+/// Returns the files removed and, for those that could not be, why they remain.
+pub fn remove_saved_keys(key_file: &Path) -> (Vec<PathBuf>, Vec<String>) {
+    remove_key_files(&[key_file, Path::new(API_KEY_SAVE_PATH)])
+}
+
+fn remove_key_files(paths: &[&Path]) -> (Vec<PathBuf>, Vec<String>) {
+    let mut removed = Vec::new();
+    let mut remaining = Vec::new();
+    for path in paths {
+        match fs::remove_file(path) {
+            Ok(_) => removed.push(path.to_path_buf()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => remaining.push(format!(
+                "Failed to remove the API key at {}, it remains: {err}",
+                path.display()
+            )),
+        }
+    }
+    (removed, remaining)
+}
+
+/// Asks for a key until one is accepted by the hub with the inference permission.
+///
+/// Errors when the hub cannot be asked or there is no terminal to ask on, a
+/// rejected key is simply asked for again.
+pub fn prompt_user_for_api_key(
+    client: &Client,
+    hub_url: &str,
+) -> Result<String, KeyValidationError> {
+    if !std::io::stdin().is_terminal() {
+        return Err(KeyValidationError::NoPrompt(
+            "stdin is not a terminal".to_string(),
+        ));
+    }
+    eprintln!(
+        "{}",
+        "Please provide the API key to HuggingFace API key to use for sentiment analysis".yellow()
+    );
+    loop {
+        match Text::new("Enter key here (should have Inference perm): ").prompt() {
+            Ok(api_key) => match validate_api_key(client, hub_url, &api_key) {
+                Ok(token_info) => {
+                    eprintln!("{}", format!("API key {token_info}").green());
+                    return Ok(api_key.trim().to_string());
+                }
+                Err(KeyValidationError::Rejected) => {
+                    eprintln!("{}", "API key validation failed. Please try again.".red());
+                }
+                Err(err @ KeyValidationError::MissingPermission(_)) => {
+                    eprintln!("{}", format!("{err}. Please try another key.").red());
+                }
+                Err(err) => return Err(err),
+            },
+            Err(OperationCanceled | OperationInterrupted) => {
+                std::process::exit(0);
+            }
+            Err(err) => return Err(KeyValidationError::NoPrompt(err.to_string())),
+        }
+    }
+}
+
+/// Save api_key_to_file at this point should have already been validated by the prompt method.
+/// The key is encrypted under a passphrase asked for here.
+pub fn save_api_key_to_file(key_file: &Path, huggingface_api_key: &str) {
+    write_encrypted_api_key(key_file, huggingface_api_key);
+}
+
+/// Asks for a new API key when the backend answers 401 (revoked or expired
+/// key) and analyzes the rejected texts again with it, so that nothing queued
+/// is lost.
+///
+/// Only one prompt is shown at a time: concurrent requests rejected with the
+/// old key wait for it and are retried with the new one. Once the user
+/// declines, later rejections are returned as they are.
+pub struct ReauthenticatingBackend {
+    inner: Arc<dyn SentimentBackend>,
+    prompt: Box<dyn KeyPrompt>,
+    key_file: PathBuf,
+    renewal: Mutex<KeyRenewal>,
+}
+
+/// How `ReauthenticatingBackend` asks for a key to replace a rejected one.
+pub trait KeyPrompt: Send + Sync {
+    /// The new key, `None` when the user would rather not give one.
+    fn new_key(&self) -> Option<String>;
+
+    /// Whether the new key should replace the saved one.
+    fn should_save(&self) -> bool;
+}
+
+/// Prompts on the terminal, validating the new key with the hub.
+struct TerminalPrompt {
+    client: Client,
+    hub_url: String,
+}
+
+impl KeyPrompt for TerminalPrompt {
+    fn new_key(&self) -> Option<String> {
+        crate::progress::clear_spinner();
+        eprintln!(
+            "{}",
+            "The API key was rejected (401), it may have been revoked or may have expired.".red()
+        );
+        let wants_new_key = Confirm::new("Enter a new API key and retry?")
+            .with_default(true)
+            .prompt()
+            .unwrap_or(false);
+        if !wants_new_key {
+            return None;
+        }
+        prompt_user_for_api_key(&self.client, &self.hub_url)
+            .map_err(|err| eprintln!("{}", err.to_string().red()))
+            .ok()
+    }
+
+    fn should_save(&self) -> bool {
+        Confirm::new("Replace the saved API key with this one?")
+            .with_default(false)
+            .with_help_message("The key is encrypted under a passphrase of your choice.")
+            .prompt()
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Default)]
+struct KeyRenewal {
+    /// Bumped on every new key, tells requests whether theirs is outdated.
+    generation: u64,
+    declined: bool,
+}
+
+impl ReauthenticatingBackend {
+    pub fn new(
+        inner: Arc<dyn SentimentBackend>,
+        client: Client,
+        hub_url: &str,
+        key_file: &Path,
+    ) -> Self {
+        let prompt = TerminalPrompt {
+            client,
+            hub_url: hub_url.to_string(),
+        };
+        Self::with_prompt(inner, Box::new(prompt), key_file)
+    }
+
+    pub fn with_prompt(
+        inner: Arc<dyn SentimentBackend>,
+        prompt: Box<dyn KeyPrompt>,
+        key_file: &Path,
+    ) -> Self {
+        ReauthenticatingBackend {
+            inner,
+            prompt,
+            key_file: key_file.to_path_buf(),
+            renewal: Mutex::new(KeyRenewal::default()),
+        }
+    }
+
+    fn renewal(&self) -> MutexGuard<'_, KeyRenewal> {
+        self.renewal
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Makes sure a key newer than `generation` is in place, prompting for it
+    /// if needed. Returns whether the rejected requests should be sent again.
+    fn renew_key(&self, generation: u64) -> bool {
+        let mut renewal = self.renewal();
+        if renewal.generation != generation {
+            // Someone else entered a new key in the meantime.
+            return true;
+        }
+        if renewal.declined {
+            return false;
+        }
+
+        let Some(api_key) = self.prompt.new_key() else {
+            renewal.declined = true;
+            return false;
+        };
+        if !self.inner.replace_api_key(&api_key) {
+            renewal.declined = true;
+            return false;
+        }
+        renewal.generation += 1;
+
+        if self.prompt.should_save() {
+            save_api_key_to_file(&self.key_file, &api_key);
+        }
+        true
+    }
+}
+
+impl SentimentBackend for ReauthenticatingBackend {
+    fn describe(&self) -> String {
+        self.inner.describe()
+    }
+
+    fn model(&self) -> String {
+        self.inner.model()
+    }
+
+    fn labels(&self) -> Vec<String> {
+        self.inner.labels()
+    }
+
+    fn analyze(&self, text: &str) -> Result<SentimentReport, BackendError> {
+        loop {
+            let generation = self.renewal().generation;
+            match self.inner.analyze(text) {
+                Err(err) if err.is_unauthorized() && self.renew_key(generation) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    fn analyze_batch(&self, texts: &[String]) -> Vec<Result<SentimentReport, BackendError>> {
+        let mut generation = self.renewal().generation;
+        let mut results = self.inner.analyze_batch(texts);
+        loop {
+            let rejected = results
+                .iter()
+                .enumerate()
+                .filter(|(_, result)| matches!(result, Err(err) if err.is_unauthorized()))
+                .map(|(index, _)| index)
+                .collect::<Vec<_>>();
+            if rejected.is_empty() || !self.renew_key(generation) {
+                return results;
+            }
+            generation = self.renewal().generation;
+            let rejected_texts = rejected
+                .iter()
+                .map(|&index| texts[index].clone())
+                .collect::<Vec<_>>();
+            let retried = self.inner.analyze_batch(&rejected_texts);
+            for (index, result) in rejected.into_iter().zip(retried) {
+                results[index] = result;
+            }
+        }
+    }
+
+    fn replace_api_key(&self, api_key: &str) -> bool {
+        self.inner.replace_api_key(api_key)
+    }
+
+    fn usage_report(&self) -> Option<String> {
+        self.inner.usage_report()
+    }
+
+    fn cache_hit_rate(&self) -> Option<f64> {
+        self.inner.cache_hit_rate()
+    }
+
+    fn finish(&self) {
+        self.inner.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{lock_env, report, serve, ScriptedBackend, TempDir};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Variables the key lookup reads, cleared before each test.
+    const VARS: &[&str] = &[
+        "HF_TOKEN",
+        "HUGGING_FACE_HUB_TOKEN",
+        "HF_TOKEN_PATH",
+        "HF_HOME",
+        "XDG_CACHE_HOME",
+        PASSPHRASE_ENV_VAR,
+    ];
+
+    fn clear_env() {
+        for var in VARS {
+            std::env::remove_var(var);
+        }
+    }
+
+    #[test]
+    fn hf_cli_token_paths_follow_the_environment() {
+        let _env = lock_env();
+        clear_env();
+        let home = dirs::home_dir().unwrap();
+        let legacy = home.join(".huggingface").join("token");
+
+        assert_eq!(
+            hf_cli_token_paths(),
+            [
+                home.join(".cache").join("huggingface").join("token"),
+                legacy.clone()
+            ]
+        );
+
+        std::env::set_var("XDG_CACHE_HOME", "/cache");
+        assert_eq!(
+            hf_cli_token_paths(),
+            [PathBuf::from("/cache/huggingface/token"), legacy.clone()]
+        );
+
+        std::env::set_var("HF_HOME", "/hf");
+        assert_eq!(
+            hf_cli_token_paths(),
+            [PathBuf::from("/hf/token"), legacy.clone()]
+        );
+
+        // Empty variables are ignored rather than pointing at `./token`.
+        std::env::set_var("HF_HOME", "");
+        std::env::set_var("XDG_CACHE_HOME", "");
+        std::env::set_var("HF_TOKEN_PATH", "");
+        assert_eq!(
+            hf_cli_token_paths(),
+            [
+                home.join(".cache").join("huggingface").join("token"),
+                legacy
+            ]
+        );
+
+        std::env::set_var("HF_TOKEN_PATH", "/elsewhere/token");
+        assert_eq!(hf_cli_token_paths(), [PathBuf::from("/elsewhere/token")]);
+        clear_env();
+    }
+
+    #[test]
+    fn keys_are_looked_up_in_order_of_precedence() {
+        let _env = lock_env();
+        clear_env();
+        let dir = TempDir::new("key-precedence");
+        let key_file = dir.join("key");
+        let hf_cli_token = dir.join("token");
+        keystore::write(
+            &key_file,
+            &EncryptedKey::seal("saved-key", "secret").unwrap(),
+        )
+        .unwrap();
+        fs::write(&hf_cli_token, "cli-token\n").unwrap();
+        std::env::set_var(PASSPHRASE_ENV_VAR, "secret");
+        std::env::set_var("HF_TOKEN_PATH", &hf_cli_token);
+        std::env::set_var("HUGGING_FACE_HUB_TOKEN", "hub-token");
+        std::env::set_var("HF_TOKEN", "env-token");
+
+        let expect = |api_key: &str, origin: KeyOrigin| {
+            assert_eq!(
+                resolve_api_key(KeySource::Auto, &key_file),
+                Some((api_key.to_string(), origin.clone()))
+            );
+            assert_eq!(locate_api_key(KeySource::Auto, &key_file), Some(origin));
+        };
+        expect("env-token", KeyOrigin::EnvVar("HF_TOKEN"));
+        // A blank variable does not hide the next one.
+        std::env::set_var("HF_TOKEN", " ");
+        expect("hub-token", KeyOrigin::EnvVar("HUGGING_FACE_HUB_TOKEN"));
+        std::env::remove_var("HUGGING_FACE_HUB_TOKEN");
+        expect("saved-key", KeyOrigin::SavedKey(key_file.clone()));
+        fs::remove_file(&key_file).unwrap();
+        expect("cli-token", KeyOrigin::HfCli(hf_cli_token.clone()));
+        fs::remove_file(&hf_cli_token).unwrap();
+        assert_eq!(resolve_api_key(KeySource::Auto, &key_file), None);
+        assert_eq!(locate_api_key(KeySource::Auto, &key_file), None);
+        clear_env();
+    }
+
+    #[test]
+    fn a_source_only_looks_at_itself() {
+        let _env = lock_env();
+        clear_env();
+        let dir = TempDir::new("key-source");
+        let key_file = dir.join("key");
+        let hf_cli_token = dir.join("token");
+        fs::write(&hf_cli_token, "cli-token").unwrap();
+        std::env::set_var("HF_TOKEN_PATH", &hf_cli_token);
+        std::env::set_var("HF_TOKEN", "env-token");
+
+        assert_eq!(
+            resolve_api_key(KeySource::HfCli, &key_file),
+            Some(("cli-token".to_string(), KeyOrigin::HfCli(hf_cli_token)))
+        );
+        assert_eq!(
+            resolve_api_key(KeySource::Env, &key_file),
+            Some(("env-token".to_string(), KeyOrigin::EnvVar("HF_TOKEN")))
+        );
+        assert_eq!(resolve_api_key(KeySource::File, &key_file), None);
+        assert_eq!(resolve_api_key(KeySource::Prompt, &key_file), None);
+        assert_eq!(
+            locate_api_key(KeySource::Prompt, &key_file),
+            Some(KeyOrigin::Prompt)
+        );
+        clear_env();
+    }
+
+    /// Body of a whoami answer for an access token.
+    fn access_token(role: &str, fine_grained: Option<&[&str]>) -> String {
+        let mut access_token = serde_json::json!({"displayName": "laptop", "role": role});
+        if let Some(global) = fine_grained {
+            access_token["fineGrained"] = serde_json::json!({"global": global, "scoped": []});
+        }
+        serde_json::json!({"name": "alice", "auth": {"accessToken": access_token}}).to_string()
+    }
+
+    fn validate(status: u16, body: String) -> Result<TokenInfo, KeyValidationError> {
+        validate_api_key(&Client::new(), &serve(vec![(status, body)]), "hf_key")
+    }
+
+    #[test]
+    fn classic_tokens_may_call_the_inference_api() {
+        for role in ["read", "write"] {
+            let token_info = validate(200, access_token(role, None)).unwrap();
+            assert_eq!(token_info.owner, "alice");
+            assert_eq!(token_info.token_name.as_deref(), Some("laptop"));
+            assert_eq!(token_info.role.as_deref(), Some(role));
+            assert!(token_info.can_infer);
+        }
+    }
+
+    #[test]
+    fn fine_grained_tokens_need_an_inference_permission() {
+        for permission in INFERENCE_PERMISSIONS {
+            let body = access_token("fineGrained", Some(&["repo.content.read", permission]));
+            assert!(validate(200, body).unwrap().can_infer, "{permission}");
+        }
+        for global in [&["repo.content.read"][..], &[]] {
+            match validate(200, access_token("fineGrained", Some(global))) {
+                Err(KeyValidationError::MissingPermission(token_info)) => {
+                    assert_eq!(token_info.role.as_deref(), Some("fineGrained"));
+                    assert!(!token_info.can_infer);
+                }
+                other => panic!("{global:?}: {other:?}"),
+            }
+        }
+        assert!(matches!(
+            validate(200, access_token("fineGrained", None)),
+            Err(KeyValidationError::MissingPermission(_))
+        ));
+    }
+
+    #[test]
+    fn a_token_the_hub_says_nothing_about_is_trusted() {
+        let token_info = validate(200, r#"{"name":"alice","auth":{}}"#.into()).unwrap();
+        assert_eq!((token_info.token_name, token_info.role), (None, None));
+        assert!(token_info.can_infer);
+    }
+
+    #[test]
+    fn hub_failures_are_told_apart_from_bad_keys() {
+        assert!(matches!(
+            validate(401, r#"{"error":"Invalid credentials"}"#.into()),
+            Err(KeyValidationError::Rejected)
+        ));
+        for status in [429, 500, 502, 503] {
+            assert!(
+                matches!(
+                    validate(status, String::new()),
+                    Err(KeyValidationError::Unreachable(_))
+                ),
+                "{status}"
+            );
+        }
+        for (status, body) in [(403, String::new()), (200, "not json".to_string())] {
+            assert!(
+                matches!(
+                    validate(status, body),
+                    Err(KeyValidationError::Unexpected(_))
+                ),
+                "{status}"
+            );
+        }
+        // Nothing listens on the port any more once the listener is dropped.
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap();
+        assert!(matches!(
+            validate_api_key(&Client::new(), &format!("http://{address}"), "hf_key"),
+            Err(KeyValidationError::Unreachable(_))
+        ));
+    }
+
+    fn unauthorized() -> BackendError {
+        BackendError::Status {
+            code: 401,
+            message: "Invalid credentials".to_string(),
+        }
+    }
+
+    /// Accepts nothing but the key it is given as a replacement.
+    struct RenewableBackend {
+        key: Mutex<String>,
+    }
+
+    impl SentimentBackend for RenewableBackend {
+        fn describe(&self) -> String {
+            "renewable".to_string()
+        }
+
+        fn model(&self) -> String {
+            "renewable".to_string()
+        }
+
+        fn labels(&self) -> Vec<String> {
+            Vec::new()
+        }
+
+        fn analyze(&self, _: &str) -> Result<SentimentReport, BackendError> {
+            match self.key.lock().unwrap().as_str() {
+                "hf_new_key" => Ok(report(0.1, 0.2, 0.7)),
+                _ => Err(unauthorized()),
+            }
+        }
+
+        fn replace_api_key(&self, api_key: &str) -> bool {
+            *self.key.lock().unwrap() = api_key.to_string();
+            true
+        }
+    }
+
+    /// Answers with `key`, counting how many times it was asked.
+    struct StubPrompt {
+        key: Option<&'static str>,
+        asked: Arc<AtomicUsize>,
+    }
+
+    impl KeyPrompt for StubPrompt {
+        fn new_key(&self) -> Option<String> {
+            self.asked.fetch_add(1, Ordering::Relaxed);
+            self.key.map(String::from)
+        }
+
+        fn should_save(&self) -> bool {
+            false
+        }
+    }
+
+    fn reauthenticating(
+        inner: Arc<dyn SentimentBackend>,
+        key: Option<&'static str>,
+    ) -> (ReauthenticatingBackend, Arc<AtomicUsize>) {
+        let asked = Arc::new(AtomicUsize::new(0));
+        let prompt = StubPrompt {
+            key,
+            asked: asked.clone(),
+        };
+        let backend =
+            ReauthenticatingBackend::with_prompt(inner, Box::new(prompt), Path::new("unused"));
+        (backend, asked)
+    }
+
+    fn renewable() -> Arc<dyn SentimentBackend> {
+        Arc::new(RenewableBackend {
+            key: Mutex::new("hf_revoked_key".to_string()),
+        })
+    }
+
+    #[test]
+    fn a_rejected_text_is_analyzed_again_with_the_new_key() {
+        let (backend, asked) = reauthenticating(renewable(), Some("hf_new_key"));
+        assert_eq!(backend.analyze("text").unwrap(), report(0.1, 0.2, 0.7));
+        assert_eq!(backend.analyze("text").unwrap(), report(0.1, 0.2, 0.7));
+        assert_eq!(asked.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn a_rejected_batch_asks_once_for_all_its_texts() {
+        let (backend, asked) = reauthenticating(renewable(), Some("hf_new_key"));
+        let results = backend.analyze_batch(&["a".to_string(), "b".to_string()]);
+        assert!(results.iter().all(Result::is_ok), "{results:?}");
+        assert_eq!(asked.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn a_declined_prompt_is_not_shown_again() {
+        let (backend, asked) =
+            reauthenticating(Arc::new(ScriptedBackend(|_| Err(unauthorized()))), None);
+        for _ in 0..2 {
+            assert!(matches!(
+                backend.analyze("text"),
+                Err(err) if err.is_unauthorized()
+            ));
+        }
+        assert_eq!(asked.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn a_key_that_cannot_be_replaced_counts_as_declined() {
+        // The scripted backend has no key to replace.
+        let (backend, asked) = reauthenticating(
+            Arc::new(ScriptedBackend(|_| Err(unauthorized()))),
+            Some("hf_new_key"),
+        );
+        assert!(backend.analyze("text").is_err());
+        assert!(backend.analyze("text").is_err());
+        assert_eq!(asked.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn clearing_removes_the_saved_and_the_legacy_keys() {
+        let dir = TempDir::new("key-clear");
+        let key_file = dir.join("key");
+        let legacy = dir.join("saved_key.txt");
+        fs::write(&key_file, "encrypted").unwrap();
+        fs::write(&legacy, "hf_plaintext").unwrap();
+
+        let (removed, remaining) = remove_key_files(&[&key_file, &legacy]);
+        assert_eq!(removed, [key_file.clone(), legacy.clone()]);
+        assert!(remaining.is_empty());
+        assert!(!key_file.exists() && !legacy.exists());
+
+        // Nothing left to remove is not an error.
+        assert_eq!(remove_key_files(&[&key_file, &legacy]), (vec![], vec![]));
+
+        // A directory in the way cannot be removed as a file.
+        fs::create_dir(&legacy).unwrap();
+        let (removed, remaining) = remove_key_files(&[&key_file, &legacy]);
+        assert!(removed.is_empty());
+        assert_eq!(remaining.len(), 1);
+        assert!(remaining[0].contains("it remains"), "{remaining:?}");
+    }
+}
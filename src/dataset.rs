@@ -0,0 +1,742 @@
+use crate::backend::BackendError;
+use crate::checkpoint::{Checkpoint, JobSettings};
+use crate::output::sentiment_fields;
+use crate::pipeline::Pipeline;
+use crate::progress::Progress;
+use crate::report::SentimentReport;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Columns appended to every row of an enriched CSV file.
+const SENTIMENT_COLUMNS: [&str; 9] = [
+    "sentiment_label",
+    "sentiment_compound",
+    "sentiment_negative",
+    "sentiment_neutral",
+    "sentiment_positive",
+    "sentiment_stars",
+    "sentiment_chunks",
+    "sentiment_model",
+    "sentiment_error",
+];
+
+/// Layout of a file given to `analyze-file`.
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InputFormat {
+    /// Comma separated values with a header row.
+    Csv,
+    /// One JSON object per line.
+    Jsonl,
+    /// One text per line.
+    Text,
+}
+
+impl InputFormat {
+    /// Guessed from the extension, anything unknown being plain text.
+    pub fn detect(path: &Path) -> Self {
+        match path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase)
+            .as_deref()
+        {
+            Some("csv") => InputFormat::Csv,
+            Some("jsonl" | "ndjson") => InputFormat::Jsonl,
+            _ => InputFormat::Text,
+        }
+    }
+
+    /// Enriched plain text is written as CSV, the other formats are kept.
+    fn output_extension(&self) -> &'static str {
+        match self {
+            InputFormat::Csv | InputFormat::Text => "csv",
+            InputFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+/// Where the text of a row is found.
+#[derive(Debug, Clone)]
+pub struct TextField {
+    /// CSV column, by header name or 1-based position.
+    pub column: String,
+    /// JSON pointer into each JSONL object (RFC 6901).
+    pub pointer: String,
+}
+
+/// The rows of a file, kept whole so that they can be written back with
+/// their sentiment appended.
+pub struct Dataset {
+    input: PathBuf,
+    input_bytes: u64,
+    format: InputFormat,
+    /// Column or JSON pointer of the text, empty for plain text.
+    text_field: String,
+    /// Header of a CSV file.
+    headers: Vec<String>,
+    rows: Vec<Row>,
+}
+
+struct Row {
+    /// Every field of a CSV row, the object of a JSONL line, or the line.
+    fields: RowFields,
+    /// Text to analyze, `None` when the row has none.
+    text: Option<String>,
+}
+
+enum RowFields {
+    Csv(Vec<String>),
+    Json(serde_json::Value),
+    Text,
+}
+
+type RowResult = Result<SentimentReport, BackendError>;
+
+impl Dataset {
+    pub fn read(path: &Path, format: InputFormat, field: &TextField) -> Result<Self, String> {
+        let file =
+            File::open(path).map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+        let input_bytes = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        let (headers, rows, text_field) = match format {
+            InputFormat::Csv => read_csv(file, &field.column)
+                .map(|(headers, rows)| (headers, rows, field.column.clone())),
+            InputFormat::Jsonl => read_jsonl(file, &field.pointer)
+                .map(|rows| (Vec::new(), rows, field.pointer.clone())),
+            InputFormat::Text => {
+                read_text(file).map(|rows| (vec!["text".to_string()], rows, String::new()))
+            }
+        }
+        .map_err(|err| format!("{}: {err}", path.display()))?;
+        Ok(Dataset {
+            input: path.to_path_buf(),
+            input_bytes,
+            format,
+            text_field,
+            headers,
+            rows,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// `data.csv` is enriched into `data.sentiment.csv` unless told otherwise.
+    pub fn default_output_path(&self, input: &Path) -> PathBuf {
+        let stem = input
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "output".to_string());
+        input.with_file_name(format!(
+            "{stem}.sentiment.{}",
+            self.format.output_extension()
+        ))
+    }
+
+    /// Identity of the job enriching this dataset with `model`.
+    pub fn job(&self, model: String) -> JobSettings {
+        JobSettings {
+            input: self.input.clone(),
+            input_bytes: self.input_bytes,
+            rows: self.rows.len(),
+            format: self.format,
+            text_field: self.text_field.clone(),
+            model,
+        }
+    }
+
+    /// Analyzes every row through `pipeline` and writes the enriched rows to
+    /// `output`, in input order, as soon as the rows before them are done.
+    ///
+    /// Progress is checkpointed every `checkpoint_every` rows. Given the
+    /// checkpoint of an interrupted run, the output is cut back to it, the
+    /// rows that failed are analyzed again and the job carries on from the
+    /// first row that was not written.
+    ///
+    /// Returns how many rows could not be analyzed.
+    pub fn enrich(
+        &self,
+        pipeline: &Pipeline,
+        job: JobSettings,
+        output: &Path,
+        resumed: Option<Checkpoint>,
+        checkpoint_every: usize,
+        progress: &mut Progress,
+    ) -> Result<usize, String> {
+        let checkpoint_path = Checkpoint::path_for(output);
+        let write_error = |e: std::io::Error| format!("Could not write {}: {e}", output.display());
+        let model = job.model.clone();
+
+        // Rows without text are not sent, `targets` maps the pipeline's
+        // indexes back to rows.
+        let first_row = resumed
+            .as_ref()
+            .map_or(0, |checkpoint| checkpoint.rows_written);
+        let targets = (first_row..self.rows.len())
+            .filter(|&index| self.rows[index].text.is_some())
+            .collect::<Vec<_>>();
+        let retried = resumed
+            .as_ref()
+            .map_or(0, |checkpoint| checkpoint.failed.len());
+        progress.set_total((retried + targets.len()) as u64);
+
+        let (mut checkpoint, file) = match resumed {
+            Some(mut checkpoint) => {
+                let file = OpenOptions::new()
+                    .write(true)
+                    .open(output)
+                    .map_err(|e| format!("Cannot resume {}: {e}", output.display()))?;
+                let output_bytes = file.metadata().map_err(write_error)?.len();
+                // Growing the file would pad it with NUL bytes.
+                if output_bytes < checkpoint.output_bytes {
+                    return Err(format!(
+                        "Cannot resume, {} is shorter than its checkpoint says",
+                        output.display()
+                    ));
+                }
+                file.set_len(checkpoint.output_bytes)
+                    .map_err(|e| format!("Cannot resume {}: {e}", output.display()))?;
+                if !checkpoint.failed.is_empty() {
+                    let failed = std::mem::take(&mut checkpoint.failed);
+                    let (still_failed, output_bytes) = self
+                        .retry(pipeline, &model, output, &failed, progress)
+                        .map_err(write_error)?;
+                    checkpoint.failed = still_failed;
+                    checkpoint.output_bytes = output_bytes;
+                    checkpoint.save(&checkpoint_path)?;
+                }
+                let mut file = OpenOptions::new()
+                    .append(true)
+                    .open(output)
+                    .map_err(write_error)?;
+                file.seek(SeekFrom::End(0)).map_err(write_error)?;
+                (checkpoint, file)
+            }
+            None => start_over(job, output)?,
+        };
+        let mut writer = RowWriter::new(self, &model, file, checkpoint.output_bytes == 0)
+            .map_err(write_error)?;
+
+        let texts = targets
+            .iter()
+            .filter_map(|&index| self.rows[index].text.clone())
+            .collect::<Vec<_>>();
+
+        let mut pending = BTreeMap::new();
+        let mut next_row = first_row;
+        let mut failed = BTreeSet::new();
+        let mut outcome = Ok(());
+        pipeline.run(texts.into_iter(), |index, _, result, _| {
+            if result.is_err() {
+                failed.insert(targets[index]);
+                progress.failure(None);
+            } else {
+                progress.success();
+            }
+            pending.insert(targets[index], result);
+            if outcome.is_ok() {
+                outcome = writer
+                    .write_ready(self, &mut pending, &mut next_row)
+                    .map_err(write_error);
+            }
+            if outcome.is_ok() && next_row - checkpoint.rows_written >= checkpoint_every {
+                outcome = writer
+                    .record_progress(&mut checkpoint, next_row, &failed)
+                    .and_then(|_| checkpoint.save(&checkpoint_path));
+            }
+        });
+        progress.finish();
+        // Rows without text after the last result, or every row when none
+        // had any text, are only written here.
+        if outcome.is_ok() {
+            outcome = writer
+                .write_ready(self, &mut pending, &mut next_row)
+                .map_err(write_error);
+        }
+        outcome?;
+        writer.record_progress(&mut checkpoint, next_row, &failed)?;
+
+        if checkpoint.failed.is_empty() && checkpoint.rows_written == self.rows.len() {
+            // Nothing left to resume.
+            let _ = fs::remove_file(&checkpoint_path);
+        } else {
+            checkpoint.save(&checkpoint_path)?;
+        }
+        Ok(checkpoint.failed.len())
+    }
+
+    /// Analyzes the `failed` rows again and rewrites `output` with their new
+    /// result, every other row being copied as is.
+    ///
+    /// Returns the rows that failed again and the new size of the output.
+    fn retry(
+        &self,
+        pipeline: &Pipeline,
+        model: &str,
+        output: &Path,
+        failed: &BTreeSet<usize>,
+        progress: &mut Progress,
+    ) -> std::io::Result<(BTreeSet<usize>, u64)> {
+        let rows = failed.iter().copied().collect::<Vec<_>>();
+        let texts = rows
+            .iter()
+            .filter_map(|&row| self.rows.get(row).and_then(|row| row.text.clone()))
+            .collect::<Vec<_>>();
+        let mut results = HashMap::new();
+        pipeline.run(texts.into_iter(), |index, _, result, _| {
+            match result {
+                Ok(_) => progress.success(),
+                Err(_) => progress.failure(None),
+            }
+            results.insert(rows[index], result);
+        });
+        let still_failed = rows
+            .iter()
+            .copied()
+            .filter(|row| !matches!(results.get(row), Some(Ok(_))))
+            .collect();
+
+        let mut temporary = output.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+        let mut writer = RowWriter::new(self, model, File::create(&temporary)?, true)?;
+        match self.format {
+            InputFormat::Csv | InputFormat::Text => {
+                let reader = csv::ReaderBuilder::new().flexible(true).from_path(output)?;
+                for (row, record) in reader.into_records().enumerate() {
+                    match (results.get(&row), self.rows.get(row)) {
+                        (Some(result), Some(original)) => writer.write(original, Some(result))?,
+                        _ => writer.copy_record(&record?)?,
+                    }
+                }
+            }
+            InputFormat::Jsonl => {
+                for (row, line) in BufReader::new(File::open(output)?).lines().enumerate() {
+                    match (results.get(&row), self.rows.get(row)) {
+                        (Some(result), Some(original)) => writer.write(original, Some(result))?,
+                        _ => writer.copy_line(&line?)?,
+                    }
+                }
+            }
+        }
+        let output_bytes = writer.sync()?;
+        fs::rename(&temporary, output)?;
+        Ok((still_failed, output_bytes))
+    }
+}
+
+/// Replaces the checkpoint of a previous run with an empty one, then truncates
+/// the output. In that order, a run interrupted before its first checkpoint
+/// is resumed from the first row rather than from the rows of the old output.
+fn start_over(job: JobSettings, output: &Path) -> Result<(Checkpoint, File), String> {
+    let mut checkpoint = Checkpoint::new(job);
+    checkpoint.save(&Checkpoint::path_for(output))?;
+    let file =
+        File::create(output).map_err(|e| format!("Could not create {}: {e}", output.display()))?;
+    Ok((checkpoint, file))
+}
+
+fn read_csv(file: File, column: &str) -> Result<(Vec<String>, Vec<Row>), String> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(file);
+    let headers = reader
+        .headers()
+        .map_err(|e| e.to_string())?
+        .iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let text_column = headers
+        .iter()
+        .position(|header| header == column)
+        .or_else(|| {
+            column
+                .parse::<usize>()
+                .ok()
+                .filter(|&position| (1..=headers.len()).contains(&position))
+                .map(|position| position - 1)
+        })
+        .ok_or_else(|| {
+            format!(
+                "no column '{column}', the columns are: {}",
+                headers.join(", ")
+            )
+        })?;
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let mut fields = record
+            .map_err(|e| e.to_string())?
+            .iter()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        // Short rows are padded so the sentiment lands in its own columns.
+        if fields.len() < headers.len() {
+            fields.resize(headers.len(), String::new());
+        }
+        let text = fields
+            .get(text_column)
+            .map(|text| text.trim().to_string())
+            .filter(|text| !text.is_empty());
+        rows.push(Row {
+            fields: RowFields::Csv(fields),
+            text,
+        });
+    }
+    Ok((headers, rows))
+}
+
+fn read_jsonl(file: File, pointer: &str) -> Result<Vec<Row>, String> {
+    // `text` is taken to mean `/text`.
+    let pointer = match pointer {
+        "" => String::new(),
+        pointer if pointer.starts_with('/') => pointer.to_string(),
+        pointer => format!("/{pointer}"),
+    };
+    let mut rows = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value: serde_json::Value =
+            serde_json::from_str(&line).map_err(|e| format!("line {}: {e}", number + 1))?;
+        let text = value
+            .pointer(&pointer)
+            .and_then(|text| text.as_str())
+            .map(|text| text.trim().to_string())
+            .filter(|text| !text.is_empty());
+        rows.push(Row {
+            fields: RowFields::Json(value),
+            text,
+        });
+    }
+    Ok(rows)
+}
+
+fn read_text(file: File) -> Result<Vec<Row>, String> {
+    let mut rows = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        let line = line.trim();
+        if !line.is_empty() {
+            rows.push(Row {
+                fields: RowFields::Text,
+                text: Some(line.to_string()),
+            });
+        }
+    }
+    Ok(rows)
+}
+
+enum RowWriter<'a> {
+    Csv {
+        writer: Box<csv::Writer<File>>,
+        model: &'a str,
+    },
+    Jsonl {
+        writer: BufWriter<File>,
+        model: &'a str,
+    },
+}
+
+impl<'a> RowWriter<'a> {
+    /// `header` is only written at the very beginning of an output.
+    fn new(dataset: &Dataset, model: &'a str, file: File, header: bool) -> std::io::Result<Self> {
+        match dataset.format {
+            InputFormat::Csv | InputFormat::Text => {
+                let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(file);
+                if header {
+                    writer.write_record(
+                        dataset
+                            .headers
+                            .iter()
+                            .map(String::as_str)
+                            .chain(SENTIMENT_COLUMNS),
+                    )?;
+                }
+                Ok(RowWriter::Csv {
+                    writer: Box::new(writer),
+                    model,
+                })
+            }
+            InputFormat::Jsonl => Ok(RowWriter::Jsonl {
+                writer: BufWriter::new(file),
+                model,
+            }),
+        }
+    }
+
+    /// Writes the rows from `next_row` on for as long as their result is known.
+    fn write_ready(
+        &mut self,
+        dataset: &Dataset,
+        pending: &mut BTreeMap<usize, RowResult>,
+        next_row: &mut usize,
+    ) -> std::io::Result<()> {
+        while let Some(row) = dataset.rows.get(*next_row) {
+            let result = match row.text {
+                None => None,
+                Some(_) => match pending.remove(next_row) {
+                    Some(result) => Some(result),
+                    None => break,
+                },
+            };
+            self.write(row, result.as_ref())?;
+            *next_row += 1;
+        }
+        self.flush()
+    }
+
+    fn write(&mut self, row: &Row, result: Option<&RowResult>) -> std::io::Result<()> {
+        match self {
+            RowWriter::Csv { writer, model } => {
+                let mut record = match &row.fields {
+                    RowFields::Csv(fields) => fields.clone(),
+                    _ => vec![row.text.clone().unwrap_or_default()],
+                };
+                record.extend(sentiment_columns(result, model));
+                writer.write_record(&record)?;
+            }
+            RowWriter::Jsonl { writer, model } => {
+                let sentiment = sentiment_object(result, model);
+                let enriched = match &row.fields {
+                    RowFields::Json(serde_json::Value::Object(object)) => {
+                        let mut object = object.clone();
+                        object.insert("sentiment".to_string(), sentiment);
+                        serde_json::Value::Object(object)
+                    }
+                    RowFields::Json(value) => json!({"input": value, "sentiment": sentiment}),
+                    _ => json!({"input": row.text, "sentiment": sentiment}),
+                };
+                writeln!(writer, "{enriched}")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies a row of a previous output (CSV).
+    fn copy_record(&mut self, record: &csv::StringRecord) -> std::io::Result<()> {
+        if let RowWriter::Csv { writer, .. } = self {
+            writer.write_record(record)?;
+        }
+        Ok(())
+    }
+
+    /// Copies a row of a previous output (JSONL).
+    fn copy_line(&mut self, line: &str) -> std::io::Result<()> {
+        if let RowWriter::Jsonl { writer, .. } = self {
+            writeln!(writer, "{line}")?;
+        }
+        Ok(())
+    }
+
+    /// Moves the checkpoint to `next_row`, once everything before it is on disk.
+    fn record_progress(
+        &mut self,
+        checkpoint: &mut Checkpoint,
+        next_row: usize,
+        failed: &BTreeSet<usize>,
+    ) -> Result<(), String> {
+        checkpoint.output_bytes = self
+            .sync()
+            .map_err(|e| format!("Could not write the output: {e}"))?;
+        checkpoint.rows_written = next_row;
+        checkpoint
+            .failed
+            .extend(failed.iter().copied().filter(|&row| row < next_row));
+        Ok(())
+    }
+
+    /// Flushes the output to disk and returns its size.
+    fn sync(&mut self) -> std::io::Result<u64> {
+        self.flush()?;
+        let file = match self {
+            RowWriter::Csv { writer, .. } => writer.get_ref(),
+            RowWriter::Jsonl { writer, .. } => writer.get_ref(),
+        };
+        file.sync_data()?;
+        Ok(file.metadata()?.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            RowWriter::Csv { writer, .. } => writer.flush(),
+            RowWriter::Jsonl { writer, .. } => writer.flush(),
+        }
+    }
+}
+
+/// Values of `SENTIMENT_COLUMNS`, all empty for a row without text.
+fn sentiment_columns(result: Option<&RowResult>, model: &str) -> Vec<String> {
+    match result {
+        None => vec![String::new(); SENTIMENT_COLUMNS.len()],
+        Some(Ok(report)) => vec![
+            report.dominant().to_string().to_lowercase(),
+            format!("{:.4}", report.compound()),
+            format!("{:.4}", report.negative_score),
+            format!("{:.4}", report.neutral_score),
+            format!("{:.4}", report.positive_score),
+            report
+                .stars
+                .map(|stars| format!("{stars:.4}"))
+                .unwrap_or_default(),
+            report.chunks.len().to_string(),
+            model.to_string(),
+            String::new(),
+        ],
+        Some(Err(err)) => {
+            let mut columns = vec![String::new(); SENTIMENT_COLUMNS.len()];
+            columns[7] = model.to_string();
+            columns[8] = err.to_string();
+            columns
+        }
+    }
+}
+
+fn sentiment_object(result: Option<&RowResult>, model: &str) -> serde_json::Value {
+    match result {
+        None => serde_json::Value::Null,
+        Some(Ok(report)) => {
+            let mut object = sentiment_fields(report);
+            object.insert("chunks".to_string(), json!(report.chunks.len()));
+            object.insert("model".to_string(), json!(model));
+            serde_json::Value::Object(object)
+        }
+        Some(Err(err)) => json!({"model": model, "error": err.to_string()}),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::Delivery;
+    use crate::testing::{report, ScriptedBackend, TempDir};
+    use std::sync::Arc;
+
+    fn analyze(text: &str) -> RowResult {
+        if text.contains("unlucky") {
+            Err(BackendError::Status {
+                code: 503,
+                message: String::new(),
+            })
+        } else {
+            Ok(report(0.1, 0.2, 0.7))
+        }
+    }
+
+    fn enrich(
+        dataset: &Dataset,
+        output: &Path,
+        resumed: Option<Checkpoint>,
+    ) -> Result<usize, String> {
+        let backend = ScriptedBackend(analyze);
+        let pipeline = Pipeline::new(Arc::new(ScriptedBackend(analyze)), 1, 2, Delivery::Ordered);
+        let mut progress = Progress::new(None, &backend, false);
+        let job = dataset.job("scripted".to_string());
+        dataset.enrich(&pipeline, job, output, resumed, 1, &mut progress)
+    }
+
+    fn text_field() -> TextField {
+        TextField {
+            column: "text".to_string(),
+            pointer: "/text".to_string(),
+        }
+    }
+
+    #[test]
+    fn resume_after_an_interrupted_fresh_run_starts_from_the_first_row() {
+        let dir = TempDir::new("resume");
+        let input = dir.join("input.csv");
+        let rows = (0..20)
+            .map(|i| format!("{i},text number {i}\n"))
+            .collect::<String>();
+        fs::write(&input, format!("id,text\n0,unlucky text\n{rows}")).unwrap();
+        let dataset = Dataset::read(&input, InputFormat::Csv, &text_field()).unwrap();
+        let output = dir.join("output.csv");
+        let checkpoint_path = Checkpoint::path_for(&output);
+
+        // A first run leaves a checkpoint behind (here because of a failed row).
+        assert_eq!(enrich(&dataset, &output, None), Ok(1));
+        let complete = fs::read(&output).unwrap();
+        assert!(checkpoint_path.exists());
+
+        // A fresh run killed right after truncating its output.
+        start_over(dataset.job("scripted".to_string()), &output).unwrap();
+
+        let checkpoint = Checkpoint::load(&checkpoint_path).unwrap().unwrap();
+        assert_eq!(checkpoint.rows_written, 0);
+        assert_eq!(enrich(&dataset, &output, Some(checkpoint)), Ok(1));
+        let resumed = fs::read(&output).unwrap();
+        assert!(!resumed.contains(&0));
+        assert_eq!(resumed, complete);
+    }
+
+    #[test]
+    fn resume_is_refused_when_the_output_is_shorter_than_the_checkpoint() {
+        let dir = TempDir::new("short-output");
+        let input = dir.join("input.txt");
+        fs::write(&input, "unlucky\nfine\n").unwrap();
+        let dataset = Dataset::read(&input, InputFormat::Text, &text_field()).unwrap();
+        let output = dir.join("output.csv");
+        assert_eq!(enrich(&dataset, &output, None), Ok(1));
+
+        let checkpoint = Checkpoint::load(&Checkpoint::path_for(&output))
+            .unwrap()
+            .unwrap();
+        fs::write(&output, "").unwrap();
+        let err = enrich(&dataset, &output, Some(checkpoint)).unwrap_err();
+        assert!(err.contains("shorter than its checkpoint"), "{err}");
+        assert_eq!(fs::read(&output).unwrap(), b"");
+    }
+
+    #[test]
+    fn jsonl_rows_keep_the_order_of_their_keys() {
+        let dir = TempDir::new("key-order");
+        let input = dir.join("input.jsonl");
+        fs::write(&input, "{\"zeta\":1,\"text\":\"fine\",\"alpha\":2}\n").unwrap();
+        let dataset = Dataset::read(&input, InputFormat::Jsonl, &text_field()).unwrap();
+        let output = dir.join("output.jsonl");
+        assert_eq!(enrich(&dataset, &output, None), Ok(0));
+        let written = fs::read_to_string(&output).unwrap();
+        assert!(
+            written.starts_with("{\"zeta\":1,\"text\":\"fine\",\"alpha\":2,\"sentiment\":"),
+            "{written}"
+        );
+    }
+
+    #[test]
+    fn rows_without_text_are_written_even_when_nothing_was_analyzed() {
+        let dir = TempDir::new("no-text");
+        let input = dir.join("input.jsonl");
+        fs::write(&input, "{\"id\":1}\n{\"id\":2}\n").unwrap();
+        let dataset = Dataset::read(&input, InputFormat::Jsonl, &text_field()).unwrap();
+        let output = dir.join("output.jsonl");
+        assert_eq!(enrich(&dataset, &output, None), Ok(0));
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "{\"id\":1,\"sentiment\":null}\n{\"id\":2,\"sentiment\":null}\n"
+        );
+        assert!(!Checkpoint::path_for(&output).exists());
+    }
+
+    #[test]
+    fn csv_scores_are_written_with_four_decimals() {
+        let dir = TempDir::new("decimals");
+        let input = dir.join("input.csv");
+        fs::write(&input, "id,text\n1,fine\n").unwrap();
+        let dataset = Dataset::read(&input, InputFormat::Csv, &text_field()).unwrap();
+        let output = dir.join("output.csv");
+        assert_eq!(enrich(&dataset, &output, None), Ok(0));
+        let written = fs::read_to_string(&output).unwrap();
+        assert_eq!(
+            written.lines().nth(1),
+            Some("1,fine,positive,0.6000,0.1000,0.2000,0.7000,,0,scripted,"),
+            "{written}"
+        );
+    }
+}
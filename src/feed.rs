@@ -0,0 +1,191 @@
+use reqwest::blocking::Client;
+use std::collections::HashSet;
+use std::fs;
+
+/// A single item extracted from an RSS/Atom feed, ready to be analyzed.
+#[derive(Debug, Clone)]
+pub struct FeedItem {
+    /// GUID (RSS) or id (Atom) of the entry, used for de-duplication.
+    pub id: String,
+    /// Where the item came from, as given by the user.
+    pub source: String,
+    /// Title and description joined together, stripped of markup.
+    pub text: String,
+}
+
+/// Keeps track of a set of feeds and of the items already handed out.
+pub struct FeedWatcher {
+    sources: Vec<String>,
+    seen: HashSet<String>,
+}
+
+impl FeedWatcher {
+    pub fn new(sources: Vec<String>) -> Self {
+        FeedWatcher {
+            sources,
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Fetches every feed once and returns the items not seen in a previous poll.
+    ///
+    /// A feed failing does not prevent the others from being read, the errors
+    /// are returned alongside the new items so the caller can report them.
+    pub fn poll(&mut self, client: &Client) -> (Vec<FeedItem>, Vec<String>) {
+        let mut new_items = Vec::new();
+        let mut errors = Vec::new();
+        for source in &self.sources {
+            match fetch_feed(client, source) {
+                Ok(items) => new_items.extend(
+                    items
+                        .into_iter()
+                        .filter(|item| self.seen.insert(item.id.clone())),
+                ),
+                Err(err) => errors.push(format!("Could not read feed {source}: {err}")),
+            }
+        }
+        (new_items, errors)
+    }
+}
+
+/// Reads a feed from an `http(s)://` URL or, failing that, from a local file.
+pub fn fetch_feed(client: &Client, source: &str) -> Result<Vec<FeedItem>, String> {
+    let raw = if source.starts_with("http://") || source.starts_with("https://") {
+        let response = client.get(source).send().map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("request failed with status {}", response.status()));
+        }
+        response.bytes().map_err(|e| e.to_string())?.to_vec()
+    } else {
+        fs::read(source).map_err(|e| e.to_string())?
+    };
+    parse_feed(source, &raw)
+}
+
+pub fn parse_feed(source: &str, raw: &[u8]) -> Result<Vec<FeedItem>, String> {
+    let feed = feed_rs::parser::parse(raw).map_err(|e| e.to_string())?;
+
+    Ok(feed
+        .entries
+        .into_iter()
+        .filter_map(|entry| {
+            let title = entry.title.map(|title| title.content);
+            let description = entry
+                .summary
+                .map(|summary| summary.content)
+                .or_else(|| entry.content.and_then(|content| content.body));
+            let text = [title, description]
+                .into_iter()
+                .flatten()
+                .map(|part| strip_markup(&part))
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join(". ");
+            (!text.is_empty()).then(|| FeedItem {
+                id: entry.id,
+                source: source.to_string(),
+                text,
+            })
+        })
+        .collect())
+}
+
+/// Descriptions are frequently HTML, the model only cares about the words.
+fn strip_markup(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::serve;
+
+    const RSS: &str = include_str!("../tests/fixtures/feed.rss");
+    const RSS_UPDATE: &str = include_str!("../tests/fixtures/feed-update.rss");
+    const ATOM: &str = include_str!("../tests/fixtures/feed.atom");
+
+    fn texts(items: &[FeedItem]) -> Vec<&str> {
+        items.iter().map(|item| item.text.as_str()).collect()
+    }
+
+    #[test]
+    fn rss_items_are_stripped_of_markup() {
+        let items = parse_feed("markets", RSS.as_bytes()).unwrap();
+        assert_eq!(
+            texts(&items),
+            [
+                "Shares rally. Stocks soared after earnings & guidance beat.",
+                "Plant closes. Hundreds of jobs lost",
+            ]
+        );
+        assert_eq!(items[0].id, "https://example.com/1");
+        assert_eq!(items[0].source, "markets");
+    }
+
+    #[test]
+    fn atom_entries_fall_back_to_their_content() {
+        let items = parse_feed("releases", ATOM.as_bytes()).unwrap();
+        assert_eq!(
+            texts(&items),
+            [
+                "Version 2 is out. A \"huge\" improvement",
+                "Known issues. Crashes on startup",
+            ]
+        );
+        assert_eq!(items[1].id, "urn:uuid:entry-2");
+    }
+
+    #[test]
+    fn invalid_feeds_are_errors() {
+        assert!(parse_feed("broken", b"<html>not a feed</html>").is_err());
+    }
+
+    #[test]
+    fn poll_only_returns_unseen_items() {
+        let url = serve(vec![(200, RSS.to_string()), (200, RSS_UPDATE.to_string())]);
+        let mut watcher = FeedWatcher::new(vec![url]);
+        let client = Client::new();
+
+        let (items, errors) = watcher.poll(&client);
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(items.len(), 2);
+
+        let (items, errors) = watcher.poll(&client);
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(texts(&items), ["Rates unchanged"]);
+    }
+
+    #[test]
+    fn a_failing_feed_does_not_hide_the_others() {
+        let failing = serve(vec![(500, String::new())]);
+        let working = serve(vec![(200, ATOM.to_string())]);
+        let mut watcher = FeedWatcher::new(vec![failing.clone(), working]);
+
+        let (items, errors) = watcher.poll(&Client::new());
+        assert_eq!(items.len(), 2);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains(&failing), "{errors:?}");
+        assert!(errors[0].contains("500"), "{errors:?}");
+    }
+}
@@ -0,0 +1,276 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// What was found in a key file.
+pub enum StoredKey {
+    Encrypted(EncryptedKey),
+    /// Written by earlier versions, to be migrated.
+    Plaintext(String),
+}
+
+/// An API key sealed with XChaCha20-Poly1305 under a key derived from a
+/// passphrase with Argon2id.
+///
+/// The KDF parameters are stored with the key so they can be raised later
+/// without breaking existing files.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EncryptedKey {
+    version: u32,
+    kdf: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    salt: String,
+    cipher: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+const FORMAT_VERSION: u32 = 1;
+const KDF: &str = "argon2id";
+const CIPHER: &str = "xchacha20poly1305";
+/// Argon2id with 19 MiB of memory and 2 passes (OWASP recommendation).
+const M_COST: u32 = 19 * 1024;
+const T_COST: u32 = 2;
+const P_COST: u32 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+impl EncryptedKey {
+    pub fn seal(api_key: &str, passphrase: &str) -> Result<Self, String> {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        getrandom::fill(&mut salt)
+            .and_then(|_| getrandom::fill(&mut nonce))
+            .map_err(|e| format!("Could not gather randomness: {e}"))?;
+
+        let cipher = cipher(passphrase, &salt, M_COST, T_COST, P_COST)?;
+        let ciphertext = cipher
+            .encrypt(&XNonce::from(nonce), api_key.as_bytes())
+            .map_err(|_| "Could not encrypt the API key".to_string())?;
+        Ok(EncryptedKey {
+            version: FORMAT_VERSION,
+            kdf: KDF.to_string(),
+            m_cost: M_COST,
+            t_cost: T_COST,
+            p_cost: P_COST,
+            salt: to_hex(&salt),
+            cipher: CIPHER.to_string(),
+            nonce: to_hex(&nonce),
+            ciphertext: to_hex(&ciphertext),
+        })
+    }
+
+    /// Decrypts the key, which fails alike on a wrong passphrase and on a tampered file.
+    pub fn open(&self, passphrase: &str) -> Result<String, String> {
+        if self.version != FORMAT_VERSION || self.kdf != KDF || self.cipher != CIPHER {
+            return Err(format!(
+                "Unsupported key file (version {}, {}, {})",
+                self.version, self.kdf, self.cipher
+            ));
+        }
+        let salt = from_hex(&self.salt)?;
+        let nonce = XNonce::try_from(from_hex(&self.nonce)?.as_slice())
+            .map_err(|_| "Invalid nonce in the key file".to_string())?;
+        let cipher = cipher(passphrase, &salt, self.m_cost, self.t_cost, self.p_cost)?;
+        let plaintext = cipher
+            .decrypt(&nonce, from_hex(&self.ciphertext)?.as_slice())
+            .map_err(|_| "Wrong passphrase (or the key file was altered)".to_string())?;
+        String::from_utf8(plaintext).map_err(|_| "The decrypted key is not valid text".to_string())
+    }
+}
+
+fn cipher(
+    passphrase: &str,
+    salt: &[u8],
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+) -> Result<XChaCha20Poly1305, String> {
+    let params = Params::new(m_cost, t_cost, p_cost, Some(32))
+        .map_err(|e| format!("Invalid key derivation parameters: {e}"))?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("Could not derive the encryption key: {e}"))?;
+    XChaCha20Poly1305::new_from_slice(&key).map_err(|e| e.to_string())
+}
+
+/// `api_key.enc` in the per-user data directory.
+pub fn default_path() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("sentiment_analyzer")
+        .join("api_key.enc")
+}
+
+/// Reads a key file, telling an encrypted key apart from a legacy plaintext one.
+pub fn read(path: &Path) -> Result<Option<StoredKey>, String> {
+    let raw = match fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(format!("Could not read {}: {err}", path.display())),
+    };
+    let raw = raw.trim();
+    if raw.is_empty() {
+        return Ok(None);
+    }
+    if raw.starts_with('{') {
+        return serde_json::from_str(raw)
+            .map(|key| Some(StoredKey::Encrypted(key)))
+            .map_err(|e| format!("Corrupted key file {}: {e}", path.display()));
+    }
+    Ok(Some(StoredKey::Plaintext(raw.to_string())))
+}
+
+/// Writes `key` to `path`, readable by the current user only.
+///
+/// The file is written next to its destination and renamed over it, so an
+/// interrupted write never leaves a truncated key behind.
+pub fn write(path: &Path, key: &EncryptedKey) -> Result<(), String> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        create_private_dir(parent)
+            .map_err(|e| format!("Could not create {}: {e}", parent.display()))?;
+    }
+    let raw = serde_json::to_string_pretty(key).map_err(|e| e.to_string())?;
+    let temporary = path.with_extension("tmp");
+    let written = create_private_file(&temporary)
+        .and_then(|mut file| file.write_all(raw.as_bytes()).and_then(|_| file.sync_all()))
+        .and_then(|_| fs::rename(&temporary, path));
+    if let Err(err) = written {
+        let _ = fs::remove_file(&temporary);
+        return Err(format!("Could not write {}: {err}", path.display()));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir)
+}
+
+#[cfg(unix)]
+fn create_private_file(path: &Path) -> std::io::Result<fs::File> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // `mode` only applies to newly created files.
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn create_private_file(path: &Path) -> std::io::Result<fs::File> {
+    fs::File::create(path)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if !hex.len().is_multiple_of(2) {
+        return Err("Invalid hexadecimal in the key file".to_string());
+    }
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or("Invalid hexadecimal in the key file".to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn a_sealed_key_opens_with_its_passphrase_only() {
+        let sealed = EncryptedKey::seal("hf_secret", "correct horse").unwrap();
+        assert!(!sealed.ciphertext.contains(&to_hex(b"hf_secret")));
+        assert_eq!(sealed.open("correct horse").unwrap(), "hf_secret");
+        let err = sealed.open("wrong horse").unwrap_err();
+        assert!(err.starts_with("Wrong passphrase"), "{err}");
+    }
+
+    #[test]
+    fn every_seal_uses_a_fresh_salt_and_nonce() {
+        let first = EncryptedKey::seal("hf_secret", "passphrase").unwrap();
+        let second = EncryptedKey::seal("hf_secret", "passphrase").unwrap();
+        assert_ne!(first.salt, second.salt);
+        assert_ne!(first.nonce, second.nonce);
+        assert_ne!(first.ciphertext, second.ciphertext);
+    }
+
+    #[test]
+    fn altered_or_unsupported_files_do_not_open() {
+        let mut sealed = EncryptedKey::seal("hf_secret", "passphrase").unwrap();
+        let flipped = if sealed.ciphertext.starts_with('0') {
+            "1"
+        } else {
+            "0"
+        };
+        sealed.ciphertext.replace_range(0..1, flipped);
+        assert!(sealed.open("passphrase").is_err());
+
+        let mut sealed = EncryptedKey::seal("hf_secret", "passphrase").unwrap();
+        sealed.version = FORMAT_VERSION + 1;
+        let err = sealed.open("passphrase").unwrap_err();
+        assert!(err.starts_with("Unsupported key file"), "{err}");
+    }
+
+    #[test]
+    fn key_files_are_told_apart() {
+        let dir = TempDir::new("keystore");
+        let path = dir.join("nested/api_key.enc");
+        assert!(matches!(read(&path), Ok(None)));
+
+        write(
+            &path,
+            &EncryptedKey::seal("hf_secret", "passphrase").unwrap(),
+        )
+        .unwrap();
+        match read(&path) {
+            Ok(Some(StoredKey::Encrypted(key))) => {
+                assert_eq!(key.open("passphrase").unwrap(), "hf_secret")
+            }
+            _ => panic!("expected an encrypted key"),
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::write(&path, "hf_legacy\n").unwrap();
+        assert!(matches!(read(&path), Ok(Some(StoredKey::Plaintext(key))) if key == "hf_legacy"));
+        fs::write(&path, "{ not json").unwrap();
+        assert!(read(&path).is_err());
+    }
+}
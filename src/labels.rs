@@ -0,0 +1,370 @@
+use crate::report::{LabelScore, ReportError, SentimentReport};
+use std::fmt;
+use LabelMeaning::{Negative, Neutral, Positive, Stars};
+
+/// What a label emitted by a model stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelMeaning {
+    Negative,
+    Neutral,
+    Positive,
+    /// A rating on a 1 to 5 star scale.
+    Stars(u8),
+}
+
+impl LabelMeaning {
+    fn parse(meaning: &str) -> Option<Self> {
+        match meaning.trim().to_lowercase().as_str() {
+            "negative" | "neg" => Some(LabelMeaning::Negative),
+            "neutral" | "neu" => Some(LabelMeaning::Neutral),
+            "positive" | "pos" => Some(LabelMeaning::Positive),
+            other => parse_stars(other).map(LabelMeaning::Stars),
+        }
+    }
+}
+
+impl fmt::Display for LabelMeaning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LabelMeaning::Negative => write!(f, "negative"),
+            LabelMeaning::Neutral => write!(f, "neutral"),
+            LabelMeaning::Positive => write!(f, "positive"),
+            LabelMeaning::Stars(1) => write!(f, "1 star"),
+            LabelMeaning::Stars(stars) => write!(f, "{stars} stars"),
+        }
+    }
+}
+
+/// Translates the labels of a given model into a `SentimentReport`.
+///
+/// Models disagree on their labels (`negative`, `LABEL_0`, `1 star`, `POSITIVE`...),
+/// the mapping is what lets every model produce the same report.
+#[derive(Debug, Clone)]
+pub struct LabelMapping {
+    entries: Vec<(String, LabelMeaning)>,
+    /// When set, every label of the mapping must be present in a response.
+    /// Only the generic fallback mapping relaxes this.
+    strict: bool,
+}
+
+impl LabelMapping {
+    /// Mapping of a known model, or the generic one recognizing the usual label names.
+    pub fn for_model(model_id: &str) -> Self {
+        LabelMapping::builtin(model_id).unwrap_or_else(LabelMapping::generic)
+    }
+
+    /// Mapping of a known model, if it is one.
+    pub fn builtin(model_id: &str) -> Option<Self> {
+        BUILTIN_MAPPINGS
+            .iter()
+            .find(|(known_model, _)| known_model.eq_ignore_ascii_case(model_id))
+            .map(|(_, entries)| LabelMapping {
+                entries: entries
+                    .iter()
+                    .map(|(label, meaning)| (label.to_string(), *meaning))
+                    .collect(),
+                strict: true,
+            })
+    }
+
+    /// Whether every one of `labels` has a meaning in the mapping.
+    #[cfg_attr(not(feature = "local-model"), allow(dead_code))]
+    pub fn covers(&self, labels: &[String]) -> bool {
+        labels.iter().all(|label| self.meaning_of(label).is_some())
+    }
+
+    /// Accepts `negative`/`neutral`/`positive` (and their abbreviations) as
+    /// well as `N star(s)` labels, without requiring any of them.
+    pub fn generic() -> Self {
+        let mut entries = vec![
+            ("negative".to_string(), LabelMeaning::Negative),
+            ("neg".to_string(), LabelMeaning::Negative),
+            ("neutral".to_string(), LabelMeaning::Neutral),
+            ("neu".to_string(), LabelMeaning::Neutral),
+            ("positive".to_string(), LabelMeaning::Positive),
+            ("pos".to_string(), LabelMeaning::Positive),
+        ];
+        entries.extend((1..=5).map(|stars| {
+            let meaning = LabelMeaning::Stars(stars);
+            (meaning.to_string(), meaning)
+        }));
+        LabelMapping {
+            entries,
+            strict: false,
+        }
+    }
+
+    /// Parses a user given mapping such as `LABEL_0=negative,LABEL_1=neutral,LABEL_2=positive`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let entries = spec
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let (label, meaning) = entry
+                    .split_once('=')
+                    .ok_or(format!("expected LABEL=meaning, got '{entry}'"))?;
+                let meaning = LabelMeaning::parse(meaning).ok_or(format!(
+                    "unknown meaning '{}' (expected negative, neutral, positive or N stars)",
+                    meaning.trim()
+                ))?;
+                Ok((label.trim().to_string(), meaning))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if entries.is_empty() {
+            return Err("label mapping is empty".to_string());
+        }
+        Ok(LabelMapping {
+            entries,
+            strict: true,
+        })
+    }
+
+    /// Labels of the mapping, in declaration order.
+    pub fn labels(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(|(label, _)| label.clone())
+            .collect()
+    }
+
+    /// `label=meaning` pairs, for display.
+    pub fn describe(&self) -> String {
+        self.entries
+            .iter()
+            .map(|(label, meaning)| format!("{label}={meaning}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn meaning_of(&self, label: &str) -> Option<LabelMeaning> {
+        self.entries
+            .iter()
+            .find(|(known_label, _)| known_label.eq_ignore_ascii_case(label))
+            .map(|(_, meaning)| *meaning)
+    }
+
+    /// Builds the report of a single input out of its label/score pairs.
+    ///
+    /// Star ratings are folded into the three classes (1-2 negative, 3 neutral,
+    /// 4-5 positive) and their expected value is kept in `SentimentReport::stars`.
+    pub fn apply(&self, label_scores: &[LabelScore]) -> Result<SentimentReport, ReportError> {
+        if label_scores.is_empty() {
+            return Err(ReportError::Empty);
+        }
+
+        let mut seen: Vec<String> = Vec::with_capacity(label_scores.len());
+        let mut report = SentimentReport {
+            neutral_score: 0.0,
+            positive_score: 0.0,
+            negative_score: 0.0,
+            stars: None,
+            chunks: Vec::new(),
+        };
+        for LabelScore { label, score } in label_scores {
+            let meaning = self
+                .meaning_of(label)
+                .ok_or_else(|| ReportError::UnexpectedLabel(label.clone()))?;
+            if seen
+                .iter()
+                .any(|seen_label| seen_label.eq_ignore_ascii_case(label))
+            {
+                return Err(ReportError::DuplicateLabel(label.clone()));
+            }
+            seen.push(label.clone());
+
+            match meaning {
+                LabelMeaning::Negative => report.negative_score += score,
+                LabelMeaning::Neutral => report.neutral_score += score,
+                LabelMeaning::Positive => report.positive_score += score,
+                LabelMeaning::Stars(stars) => {
+                    match stars {
+                        1 | 2 => report.negative_score += score,
+                        3 => report.neutral_score += score,
+                        _ => report.positive_score += score,
+                    }
+                    *report.stars.get_or_insert(0.0) += stars as f64 * score;
+                }
+            }
+        }
+
+        if self.strict {
+            if let Some((missing, _)) = self.entries.iter().find(|(label, _)| {
+                !seen
+                    .iter()
+                    .any(|seen_label| seen_label.eq_ignore_ascii_case(label))
+            }) {
+                return Err(ReportError::MissingLabel(missing.clone()));
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// `"4 stars"`, `"1 star"` or `"4"` to `4`, for ratings between 1 and 5.
+fn parse_stars(label: &str) -> Option<u8> {
+    let number = label
+        .trim_end_matches("stars")
+        .trim_end_matches("star")
+        .trim();
+    number
+        .parse::<u8>()
+        .ok()
+        .filter(|stars| (1..=5).contains(stars))
+}
+
+static THREE_WAY: &[(&str, LabelMeaning)] = &[
+    ("negative", Negative),
+    ("neutral", Neutral),
+    ("positive", Positive),
+];
+
+static BINARY_UPPERCASE: &[(&str, LabelMeaning)] =
+    &[("NEGATIVE", Negative), ("POSITIVE", Positive)];
+
+/// Label sets of popular sentiment models on the HuggingFace hub.
+static BUILTIN_MAPPINGS: &[(&str, &[(&str, LabelMeaning)])] = &[
+    (
+        "cardiffnlp/twitter-roberta-base-sentiment-latest",
+        THREE_WAY,
+    ),
+    ("cardiffnlp/twitter-xlm-roberta-base-sentiment", THREE_WAY),
+    (
+        "cardiffnlp/twitter-roberta-base-sentiment",
+        &[
+            ("LABEL_0", Negative),
+            ("LABEL_1", Neutral),
+            ("LABEL_2", Positive),
+        ],
+    ),
+    (
+        "nlptown/bert-base-multilingual-uncased-sentiment",
+        &[
+            ("1 star", Stars(1)),
+            ("2 stars", Stars(2)),
+            ("3 stars", Stars(3)),
+            ("4 stars", Stars(4)),
+            ("5 stars", Stars(5)),
+        ],
+    ),
+    (
+        "distilbert/distilbert-base-uncased-finetuned-sst-2-english",
+        BINARY_UPPERCASE,
+    ),
+    (
+        "distilbert-base-uncased-finetuned-sst-2-english",
+        BINARY_UPPERCASE,
+    ),
+    ("siebert/sentiment-roberta-large-english", BINARY_UPPERCASE),
+    (
+        "finiteautomata/bertweet-base-sentiment-analysis",
+        &[("NEG", Negative), ("NEU", Neutral), ("POS", Positive)],
+    ),
+    ("ProsusAI/finbert", THREE_WAY),
+    (
+        "mrm8488/distilroberta-finetuned-financial-news-sentiment-analysis",
+        THREE_WAY,
+    ),
+    (
+        "lxyuan/distilbert-base-multilingual-cased-sentiments-student",
+        THREE_WAY,
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scores(pairs: &[(&str, f64)]) -> Vec<LabelScore> {
+        pairs
+            .iter()
+            .map(|(label, score)| LabelScore {
+                label: label.to_string(),
+                score: *score,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn model_labels_are_mapped_case_insensitively() {
+        let mapping = LabelMapping::for_model("cardiffnlp/twitter-roberta-base-sentiment");
+        let report = mapping
+            .apply(&scores(&[
+                ("label_2", 0.6),
+                ("LABEL_1", 0.3),
+                ("LABEL_0", 0.1),
+            ]))
+            .unwrap();
+        assert_eq!(report.positive_score, 0.6);
+        assert_eq!(report.neutral_score, 0.3);
+        assert_eq!(report.negative_score, 0.1);
+        assert_eq!(report.stars, None);
+    }
+
+    #[test]
+    fn strict_mappings_require_every_label() {
+        let mapping = LabelMapping::for_model("ProsusAI/finbert");
+        let result = mapping.apply(&scores(&[("positive", 0.6), ("negative", 0.4)]));
+        assert!(matches!(result, Err(ReportError::MissingLabel(label)) if label == "neutral"));
+    }
+
+    #[test]
+    fn the_generic_mapping_accepts_partial_label_sets() {
+        let report = LabelMapping::generic()
+            .apply(&scores(&[("POS", 0.8), ("neg", 0.2)]))
+            .unwrap();
+        assert_eq!(report.positive_score, 0.8);
+        assert_eq!(report.negative_score, 0.2);
+        assert_eq!(report.neutral_score, 0.0);
+    }
+
+    #[test]
+    fn unknown_and_duplicate_labels_are_rejected() {
+        let mapping = LabelMapping::generic();
+        assert!(matches!(
+            mapping.apply(&scores(&[("positive", 0.5), ("sarcastic", 0.5)])),
+            Err(ReportError::UnexpectedLabel(label)) if label == "sarcastic"
+        ));
+        assert!(matches!(
+            mapping.apply(&scores(&[("positive", 0.5), ("Positive", 0.5)])),
+            Err(ReportError::DuplicateLabel(label)) if label == "Positive"
+        ));
+        assert!(matches!(mapping.apply(&[]), Err(ReportError::Empty)));
+    }
+
+    #[test]
+    fn star_ratings_are_folded_into_the_three_classes() {
+        let mapping = LabelMapping::for_model("nlptown/bert-base-multilingual-uncased-sentiment");
+        let report = mapping
+            .apply(&scores(&[
+                ("1 star", 0.1),
+                ("2 stars", 0.1),
+                ("3 stars", 0.2),
+                ("4 stars", 0.2),
+                ("5 stars", 0.4),
+            ]))
+            .unwrap();
+        assert!((report.negative_score - 0.2).abs() < 1e-9);
+        assert!((report.neutral_score - 0.2).abs() < 1e-9);
+        assert!((report.positive_score - 0.6).abs() < 1e-9);
+        // 0.1 + 0.2 + 0.6 + 0.8 + 2.0
+        assert!((report.stars.unwrap() - 3.7).abs() < 1e-9);
+    }
+
+    #[test]
+    fn user_mappings_are_parsed() {
+        let mapping = LabelMapping::parse("LABEL_0=negative, LABEL_1 = 3 stars").unwrap();
+        assert_eq!(mapping.labels(), ["LABEL_0", "LABEL_1"]);
+        assert_eq!(mapping.describe(), "LABEL_0=negative, LABEL_1=3 stars");
+        assert!(LabelMapping::parse("LABEL_0").is_err());
+        assert!(LabelMapping::parse("LABEL_0=angry").is_err());
+        assert!(LabelMapping::parse("LABEL_0=6 stars").is_err());
+        assert!(LabelMapping::parse(" , ").is_err());
+    }
+
+    #[test]
+    fn unknown_models_get_the_generic_mapping() {
+        let mapping = LabelMapping::for_model("someone/new-model");
+        assert!(!mapping.strict);
+        assert!(mapping.labels().contains(&"5 stars".to_string()));
+    }
+}
@@ -1,11 +1,42 @@
-use colorize::AnsiColor;
 use inquire::InquireError::{OperationCanceled, OperationInterrupted};
-use inquire::{Confirm, Select, Text};
+use inquire::{Confirm, CustomType, Select, Text};
 use reqwest::blocking::Client;
-use serde_json::json;
-use std::fs;
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use strum_macros::Display;
 
+mod backend;
+mod cache;
+mod checkpoint;
+mod chunking;
+mod cli;
+mod config;
+mod credentials;
+mod dataset;
+mod feed;
+mod keystore;
+mod labels;
+mod output;
+mod pipeline;
+mod progress;
+mod render;
+mod report;
+mod session;
+#[cfg(test)]
+mod testing;
+
+use backend::{BackendKind, BackendSettings, SentimentBackend};
+use clap::{CommandFactory, FromArgMatches};
+use cli::{finish_run, follow_feeds, write_result, Cli, KeySource};
+use credentials::{prompt_user_for_api_key, resolve_api_key, save_api_key_to_file};
+use output::ResultWriter;
+use pipeline::Pipeline;
+use progress::Spinner;
+use render::{Renderer, Tint};
+use session::{ExportFormat, Session};
+
 #[derive(Display, Debug)]
 enum ProtocolOptions {
     Online,
@@ -15,11 +46,6 @@ enum ProtocolOptions {
 
 use ProtocolOptions::*;
 
-struct SentimentReport {
-    neutral_score: f64,
-    positive_score: f64,
-    negative_score: f64,
-}
 /// input feed protocol loop
 /// Take input
 /// Process it (give user some kind of waiting prompt to account for delay)
@@ -33,7 +59,12 @@ struct SentimentReport {
 ///
 /// It will keep running until user terminates or an unrecoverable error occurs.
 /// Alternatively allow use to go from user input strings to online feed.
-fn user_input_feed_protocol(client: &Client, huggingface_api_key: &str) {
+fn user_input_feed_protocol(
+    backend: &dyn SentimentBackend,
+    renderer: &Renderer,
+    no_progress: bool,
+) {
+    let mut session = Session::new(backend.model());
     loop {
         // retrieve from user
         let user_post = match Text::new(
@@ -47,6 +78,8 @@ fn user_input_feed_protocol(client: &Client, huggingface_api_key: &str) {
                     "{}",
                     "Received termination signal. Program will now gracefully terminate.".yellow()
                 );
+                finish_run(backend);
+                offer_session_export(&session);
                 std::process::exit(0);
             }
             Err(err) => {
@@ -55,163 +88,276 @@ fn user_input_feed_protocol(client: &Client, huggingface_api_key: &str) {
             }
         };
 
-        match sentiment_analysis_request(client, &user_post, huggingface_api_key) {
-            Ok(sentiment_analysis) => {
-                dbg!(sentiment_analysis);
-            }
-            Err(_) => {
-                let try_again = match Confirm::new(
-                    "The prompt sentiment analysis failed. Do you want to try again?",
-                )
-                .with_help_message(
-                    "If not program will terminate (in case of failure you will be prompted again)",
-                )
-                .prompt()
-                {
-                    Ok(should_try_again) => should_try_again,
-                    Err(OperationCanceled | OperationInterrupted) => {
+        // Analyze until it works or the user gives up on this text.
+        loop {
+            let started = Instant::now();
+            let analyzed = {
+                let _spinner = (!no_progress).then(|| Spinner::start("Analyzing..."));
+                backend.analyze(&user_post)
+            };
+            match analyzed {
+                Ok(sentiment_report) => {
+                    println!("{}", renderer.render(&user_post, &sentiment_report));
+                    session.record(&user_post, &sentiment_report, started.elapsed());
+                    break;
+                }
+                Err(err) => {
+                    eprintln!("{}", err.to_string().red());
+                    let try_again = match Confirm::new(
+                        "The prompt sentiment analysis failed. Do you want to try again?",
+                    )
+                    .with_help_message(
+                        "If not program will terminate (in case of failure you will be prompted again)",
+                    )
+                    .prompt()
+                    {
+                        Ok(should_try_again) => should_try_again,
+                        Err(OperationCanceled | OperationInterrupted) => {
+                            eprintln!(
+                                "{}",
+                                "A termination signal has been sent. Program will terminate.".red()
+                            );
+                            finish_run(backend);
+                            offer_session_export(&session);
+                            std::process::exit(-1);
+                        }
+                        Err(err) => {
+                            eprintln!("An error occurred as we were awaiting confirmation from user : {err}\nProgram will now terminate");
+                            std::process::exit(-1);
+                        }
+                    };
+
+                    if !try_again {
                         eprintln!(
                             "{}",
-                            "A termination signal has been sent. Program will terminate.".red()
+                            "Received termination signal. Program will now gracefully terminate."
+                                .yellow()
                         );
-                        std::process::exit(-1);
-                    }
-                    Err(err) => {
-                        eprintln!("An error occurred as we were awaiting confirmation from user : {err}\nProgram will now terminate");
-                        std::process::exit(-1);
+                        finish_run(backend);
+                        offer_session_export(&session);
+                        std::process::exit(0);
                     }
-                };
-
-                if try_again {
-                    todo!("Not yet implemented")
                 }
             }
+        }
+    }
+}
+
+/// Analyzes every record piped on stdin, without any prompt.
+///
+/// Records are separated by newlines, or by NUL bytes with `null_delimited`
+/// (`find -print0`, `xargs -0`). Each result is written as soon as its record
+/// was read, so `tail -f app.log | sentiment_analyzer --stdin` keeps up with
+/// the log. Returns the exit code once stdin is closed.
+///
+/// Like a subcommand, the backend defaults to the Inference API and the key
+/// is looked up without prompting, stdin being taken by the records.
+fn stdin_feed_protocol(cli: &Cli, client: &Client, renderer: Renderer) -> i32 {
+    if cli.key_source == KeySource::Prompt {
+        eprintln!(
+            "{}",
+            "--key-source prompt cannot be used with --stdin, stdin holds the records.".red()
+        );
+        return 2;
+    }
+    let backend_kind = cli.backend.unwrap_or(BackendKind::HuggingFace);
+    let backend = match cli::build_backend(cli, client, backend_kind) {
+        Ok(backend) => backend,
+        Err(exit_code) => return exit_code,
+    };
+    let mut output = open_result_writer(cli, renderer, backend.as_ref());
+    let delimiter = if cli.null { b'\0' } else { b'\n' };
+    let mut exit_code = 0;
+    for text in records(std::io::stdin().lock(), delimiter) {
+        let text = match text {
+            Ok(text) => text,
+            Err(err) => {
+                eprintln!("{}", format!("Could not read stdin: {err}").red());
+                exit_code = 1;
+                break;
+            }
         };
+        let started = Instant::now();
+        match backend.analyze(&text) {
+            Ok(sentiment_report) => write_result(
+                &mut output,
+                &text,
+                &sentiment_report,
+                started.elapsed(),
+                None,
+            ),
+            Err(err) => {
+                eprintln!("{}", err.to_string().red());
+                exit_code = 1;
+            }
+        }
+    }
+    if let Err(err) = output.finish() {
+        eprintln!("{}", format!("Could not write the results: {err}").red());
+        exit_code = 1;
     }
+    finish_run(backend.as_ref());
+    exit_code
 }
 
-fn online_feed_protocol() {}
+/// The records of `input` separated by `delimiter`, trimmed, blank ones
+/// skipped. A trailing delimiter does not make an extra record.
+fn records(input: impl BufRead, delimiter: u8) -> impl Iterator<Item = std::io::Result<String>> {
+    input
+        .split(delimiter)
+        .map(|record| record.map(|record| String::from_utf8_lossy(&record).trim().to_string()))
+        .filter(|record| !matches!(record, Ok(text) if text.is_empty()))
+}
 
-fn sentiment_analysis_request(
-    client: &Client,
-    text: &str,
-    api_key: &str,
-) -> Result<String, String> {
-    let response = client
-        .post(DEFAULT_MODEL_PATH)
-        .bearer_auth(api_key)
-        .json(&json!({"inputs":text}))
-        .send()
-        .map_err(|e| e.to_string())?;
-
-    if response.status().is_success() {
-        let sentiment_analysis = response
-            .text()
-            .expect("Should have been able to read sentiment analysis.");
-        Ok(sentiment_analysis)
-    } else {
-        let error_message = format!(
-            "Sentiment analysis failed with status {}",
-            response.status()
-        )
-        .red();
-        Err(error_message)
+/// Opens the output of `--format`/`--output`, terminating the program if it cannot be created.
+fn open_result_writer(
+    cli: &Cli,
+    renderer: Renderer,
+    backend: &dyn SentimentBackend,
+) -> ResultWriter {
+    match ResultWriter::open(cli.format, renderer, backend.model(), cli.output.as_deref()) {
+        Ok(output) => output,
+        Err(err) => {
+            eprintln!("{}", err.red());
+            std::process::exit(-1);
+        }
     }
 }
 
-fn check_for_api_key_file() -> (bool, String) {
-    let api_key = fs::read_to_string(API_KEY_SAVE_PATH).unwrap_or_default();
+/// Offers to save what was analyzed during the session before terminating.
+///
+/// Any failure here is reported but never prevents the program from terminating.
+fn offer_session_export(session: &Session) {
+    if session.is_empty() {
+        return;
+    }
+    let should_save = Confirm::new(&format!(
+        "Save the {} analysis results of this session to a file?",
+        session.len()
+    ))
+    .with_default(false)
+    .prompt()
+    .unwrap_or(false);
+    if !should_save {
+        return;
+    }
 
-    if api_key.is_empty() {
-        (false, api_key)
-    } else {
-        (true, api_key)
+    let format = match Select::new(
+        "Export format: ",
+        vec![ExportFormat::Csv, ExportFormat::Json, ExportFormat::Ndjson],
+    )
+    .prompt()
+    {
+        Ok(format) => format,
+        Err(_) => return,
+    };
+    let default_path = format!("sentiment_session.{}", format.extension());
+    let path = match Text::new("Save to: ").with_default(&default_path).prompt() {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => return,
+    };
+
+    match session.export(&path, format) {
+        Ok(_) => println!("Saved analysis results to {}", path.display()),
+        Err(err) => eprintln!(
+            "{}",
+            format!("Failed to save analysis results: {err}").red()
+        ),
     }
 }
 
-fn prompt_user_for_api_key(client: &Client) -> Result<String, reqwest::Error> {
-    println!(
-        "{}",
-        format!("Please provide the API key to HuggingFace API key to use for sentiment analysis")
-            .yellow()
-    );
-    loop {
-        match Text::new("Enter key here (should have Inference perm): ").prompt() {
-            Ok(api_key) => {
-                let payload = json!({"inputs": "Hello, I will make money, retire my parents, and escape from the rat race. Then I'll learn mandarin."});
-
-                let response = client
-                    .post(DEFAULT_MODEL_PATH)
-                    .bearer_auth(&api_key)
-                    .json(&payload)
-                    .send()?;
-
-                if response.status().is_success() {
-                    return Ok(api_key);
-                } else {
-                    eprintln!("{}", "API key validation failed. Please try again.".red());
-                }
-            }
+/// online feed protocol loop
+/// Ask for the feeds (RSS/Atom URLs or local files) and the polling interval
+/// Poll every feed, analyze the items we have not seen yet and print them
+/// Sleep until the next poll and run the loop again
+///
+/// This function serves as a second main if user selected
+/// the Online option for the source of data.
+///
+/// It will keep running until the process is interrupted (Ctrl-C).
+fn online_feed_protocol(
+    client: &Client,
+    cli: &Cli,
+    pipeline: &Pipeline,
+    output: &mut ResultWriter,
+) {
+    let default_feeds = cli.feeds.join(", ");
+    let sources =
+        match Text::new("Enter the feeds to follow (URLs or file paths, comma separated): ")
+            .with_default(&default_feeds)
+            .prompt()
+        {
+            Ok(sources) => sources
+                .split(',')
+                .map(|source| source.trim().to_string())
+                .filter(|source| !source.is_empty())
+                .collect::<Vec<_>>(),
             Err(OperationCanceled | OperationInterrupted) => {
+                eprintln!(
+                    "{}",
+                    "Received termination signal. Program will now gracefully terminate.".yellow()
+                );
                 std::process::exit(0);
             }
             Err(err) => {
-                eprintln!(
-                    "{}",
-                    format!("Some error {err} occurred as we were awaiting API Key").red()
-                )
+                eprintln!("{}", format!("An error ocurred: {err}").red());
+                std::process::exit(-1);
             }
-        }
+        };
+    if sources.is_empty() {
+        eprintln!("{}", "No feed was given. Program will now terminate.".red());
+        std::process::exit(-1);
     }
-}
 
-///
-/// Save api_key_to_file at this point should have already been validated by the prompt method
-fn save_api_key_to_file(huggingface_api_key: &str) {
-    match fs::write(API_KEY_SAVE_PATH, huggingface_api_key) {
-        Ok(_) => {
-            println!("Saved API key succesfully");
-        }
-        Err(e) => {
-            eprintln!("{}", format!("Failed to save API key: {e}").red());
+    let poll_interval = match CustomType::<u64>::new("Polling interval in seconds: ")
+        .with_default(cli.poll_interval)
+        .prompt()
+    {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(OperationCanceled | OperationInterrupted) => std::process::exit(0),
+        Err(err) => {
+            eprintln!("{}", format!("An error ocurred: {err}").red());
+            std::process::exit(-1);
         }
     };
-}
 
-static API_KEY_SAVE_PATH: &str = "./saved_key.txt";
-static DEFAULT_MODEL_PATH: &str =
-    "https://api-inference.huggingface.co/models/cardiffnlp/twitter-roberta-base-sentiment-latest";
+    let show_progress = progress::visible(cli.no_progress);
+    follow_feeds(
+        client,
+        pipeline,
+        output,
+        sources,
+        poll_interval,
+        false,
+        show_progress,
+    );
+}
 
-/// Entry point of the program.
-///
-/// Takes inputs from the user and then redirect to the proper function.
-///
-/// 1. Get API key from USER (or file)
-/// 2. Get a feed of data
-/// 3. Run the transformer on the sentiment classifier
-///
-/// We want the ability:
-///  - to pass either a user feed and classify sentiment of what user says
-///  - taking a feed of data (say Tweeter/RSS) and then evaluating the sentiment of the thing
-///
-/// This could be easily used as a component for a trade signal given the right feed.
-fn main() {
-    //General Command Flow
+/// Steps I and II of the interactive flow: find a key (or ask for one) and
+/// offer to save it when it was typed in.
+fn obtain_api_key(client: &Client, cli: &Cli) -> String {
+    let key_source = cli.key_source;
     //I. Check that saved API key exists, and if it does retrieve it (else prompt user)
     // For the former just check if the file exists for the latter just prompt for a key and attempt connection.
-    let (api_key_was_saved, api_key_from_file) = check_for_api_key_file();
-    let client = Client::new();
-    let huggingface_api_key = if api_key_was_saved {
-        api_key_from_file
+    let api_key_from_source = resolve_api_key(key_source, &cli.key_file);
+    let api_key_was_saved = api_key_from_source.is_some();
+    let huggingface_api_key = if let Some((api_key, origin)) = api_key_from_source {
+        println!("Using the API key from {origin}");
+        api_key
     } else {
-        prompt_user_for_api_key(&client)
-            .expect("Should have been able to get the content from API key if valid")
+        match prompt_user_for_api_key(client, &cli.hub_url) {
+            Ok(api_key) => api_key,
+            Err(err) => {
+                eprintln!("{}", format!("{err}\nProgram will now terminate").red());
+                std::process::exit(-1);
+            }
+        }
     };
-    if !api_key_was_saved {
+    if !api_key_was_saved && key_source != KeySource::Env {
         //II. Ask user if we should save it, and if so we save:
         let should_save_api_key_to_file = match Confirm::new("Should we save the API_KEY File")
             .with_default(false)
-            .with_help_message("In this implementation, API key is not encrypted.")
+            .with_help_message("The key is encrypted under a passphrase of your choice.")
             .prompt()
         {
             Ok(reply) => reply,
@@ -224,9 +370,122 @@ fn main() {
         };
 
         if should_save_api_key_to_file {
-            save_api_key_to_file(&huggingface_api_key);
+            save_api_key_to_file(&cli.key_file, &huggingface_api_key);
         }
     }
+    huggingface_api_key
+}
+
+/// Entry point of the program.
+///
+/// Takes inputs from the user and then redirect to the proper function.
+///
+/// 1. Get API key from USER (or file)
+/// 2. Get a feed of data
+/// 3. Run the transformer on the sentiment classifier
+///
+/// We want the ability:
+///  - to pass either a user feed and classify sentiment of what user says
+///  - taking a feed of data (say Tweeter/RSS) and then evaluating the sentiment of the thing
+///
+/// This could be easily used as a component for a trade signal given the right feed.
+///
+/// When a subcommand is given on the command line, none of the menus are shown
+/// and the program runs that command instead (see `cli`).
+fn main() {
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches)
+        .and_then(|cli| cli.check_conflicts().map(|_| cli))
+        .unwrap_or_else(|err| err.exit());
+    // Decided again once the configuration, which can turn colour off, is read.
+    render::set_message_color(cli.no_color);
+    if let Err(err) = config::apply(&mut cli, &matches) {
+        eprintln!("{}", err.red());
+        std::process::exit(2);
+    }
+    render::set_message_color(cli.no_color);
+    let client = match Client::builder()
+        .timeout((cli.timeout > 0).then(|| Duration::from_secs(cli.timeout)))
+        .build()
+    {
+        Ok(client) => client,
+        Err(err) => {
+            eprintln!(
+                "{}",
+                format!("Could not set up the HTTP client: {err}").red()
+            );
+            std::process::exit(-1);
+        }
+    };
+    if let Some(command) = cli.command.take() {
+        std::process::exit(cli::run_command(command, &cli, &client));
+    }
+    let renderer = Renderer::new(cli.no_color);
+    if cli.stdin {
+        std::process::exit(stdin_feed_protocol(&cli, &client, renderer));
+    }
+
+    //General Command Flow
+    //0. Pick the backend, only the remote one needs an API key.
+    let backend_kind = match cli.backend {
+        Some(backend_kind) => backend_kind,
+        None => match Select::new(
+            "Which backend should run the analysis?: ",
+            BackendKind::available(),
+        )
+        .prompt()
+        {
+            Ok(backend_kind) => backend_kind,
+            Err(OperationCanceled | OperationInterrupted) => {
+                println!("Operation was interrupted or escaped. Terminating.");
+                std::process::exit(1);
+            }
+            Err(err) => {
+                eprintln!("{}", format!("An error occured as we were waiting for backend selection. \nError Code: {err}\n\nProgram will now terminate").red());
+                std::process::exit(-1);
+            }
+        },
+    };
+    let huggingface_api_key = if backend_kind.needs_api_key() && cli.key_pool.is_none() {
+        obtain_api_key(&client, &cli)
+    } else {
+        String::new()
+    };
+    let model_dir = match &cli.model_dir {
+        Some(model_dir) => Some(model_dir.clone()),
+        None if backend_kind.needs_model_dir() => {
+            match Text::new(
+                "Directory of the local model (model.onnx, tokenizer.json, config.json): ",
+            )
+            .prompt()
+            {
+                Ok(model_dir) => Some(PathBuf::from(model_dir)),
+                Err(OperationCanceled | OperationInterrupted) => std::process::exit(0),
+                Err(err) => {
+                    eprintln!("{}", format!("An error ocurred: {err}").red());
+                    std::process::exit(-1);
+                }
+            }
+        }
+        None => None,
+    };
+    let backend_settings = BackendSettings {
+        model_dir,
+        ..cli.backend_settings(huggingface_api_key)
+    };
+    let backend: Arc<dyn SentimentBackend> = match backend_kind.build(&client, &backend_settings) {
+        Ok(backend) => cli.with_chunking(
+            backend_kind,
+            cli.with_cache(
+                backend_kind,
+                cli.with_reauthentication(&client, Arc::from(backend)),
+            ),
+        ),
+        Err(err) => {
+            eprintln!("{}", format!("Could not start the backend: {err}").red());
+            std::process::exit(-1);
+        }
+    };
 
     //III. Prompt the user for which path we should elect and jump to the respective logic.
     let protocol_options = vec![Online, User, Quit];
@@ -238,8 +497,15 @@ fn main() {
 
     match protocol_selection {
         Ok(choice) => match choice {
-            Online => online_feed_protocol(),
-            User => user_input_feed_protocol(&client, &huggingface_api_key),
+            Online => {
+                let mut output = open_result_writer(&cli, renderer, backend.as_ref());
+                online_feed_protocol(&client, &cli, &cli.pipeline(backend.clone()), &mut output);
+                if let Err(err) = output.finish() {
+                    eprintln!("{}", format!("Could not write the results: {err}").red());
+                }
+                finish_run(backend.as_ref());
+            }
+            User => user_input_feed_protocol(backend.as_ref(), &renderer, cli.no_progress),
             Quit => std::process::exit(0),
         },
         Err(OperationCanceled | OperationInterrupted) => {
@@ -247,8 +513,42 @@ fn main() {
             std::process::exit(1);
         }
         Err(err) => {
-            println!("{}", format!("An error occured as we were waiting for protocol selection. \nError Code: {err}\n\nProgram will now terminate").red());
+            eprintln!("{}", format!("An error occured as we were waiting for protocol selection. \nError Code: {err}\n\nProgram will now terminate").red());
             std::process::exit(-1);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(input: &[u8], delimiter: u8) -> Vec<String> {
+        records(input, delimiter)
+            .collect::<std::io::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn records_are_split_on_newlines() {
+        assert_eq!(split(b"good\nbad\n", b'\n'), ["good", "bad"]);
+        assert_eq!(split(b"good\r\n  bad  ", b'\n'), ["good", "bad"]);
+        assert_eq!(split(b"\n\ngood\n \t \n\nbad\n\n", b'\n'), ["good", "bad"]);
+        assert!(split(b"", b'\n').is_empty());
+        assert!(split(b"\n", b'\n').is_empty());
+    }
+
+    #[test]
+    fn records_are_split_on_nul_bytes() {
+        assert_eq!(
+            split(b"first line\nsame record\0second\0", b'\0'),
+            ["first line\nsame record", "second"]
+        );
+        assert_eq!(split(b"\0\0only\0 \0", b'\0'), ["only"]);
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced_rather_than_dropped() {
+        assert_eq!(split(b"caf\xe9\n", b'\n'), ["caf\u{fffd}"]);
+    }
+}
@@ -0,0 +1,383 @@
+use crate::render::{snippet, Renderer};
+use crate::report::SentimentReport;
+use chrono::Utc;
+use clap::ValueEnum;
+use indicatif::ProgressBar;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Coloured, human readable summary.
+    Human,
+    /// Aligned columns, one row per analyzed text.
+    Table,
+    /// A single JSON array, closed once every text was analyzed.
+    Json,
+    /// One JSON object per line, usable while results are still coming in.
+    Ndjson,
+    /// Comma separated values with a header row.
+    Csv,
+}
+
+/// Width of the input column of the table format.
+const TABLE_INPUT_WIDTH: usize = 48;
+
+/// Writes analysis results to stdout or a file in the requested format.
+///
+/// Every result is flushed as soon as it is written, so the output can be
+/// followed (`tail -f`, `jq`) while a long batch is still running.
+pub struct ResultWriter {
+    format: OutputFormat,
+    renderer: Renderer,
+    /// Model the results come from, repeated in every record.
+    model: String,
+    writer: Box<dyn Write>,
+    /// Results go to stdout and stdout is a terminal.
+    on_terminal: bool,
+    /// Progress bar drawn on that same terminal, complete lines are printed
+    /// above it and the rest is held in `pending`.
+    above: Option<ProgressBar>,
+    pending: String,
+    written: usize,
+}
+
+/// One analyzed text, as the machine readable formats describe it.
+///
+/// The results of a run and the export of an interactive session are both
+/// written through it, so that they share one schema.
+pub struct AnalysisRecord<'a> {
+    pub input: &'a str,
+    pub report: &'a SentimentReport,
+    /// Feed the text was taken from, if any.
+    pub source: Option<&'a str>,
+    pub model: &'a str,
+    pub timestamp: String,
+    pub latency: Duration,
+}
+
+/// Flat view of a record, CSV has no nesting.
+#[derive(Serialize)]
+pub struct CsvRow<'a> {
+    input: &'a str,
+    label: String,
+    compound: f64,
+    negative: f64,
+    neutral: f64,
+    positive: f64,
+    stars: Option<f64>,
+    /// Number of chunks a long input was split into, 0 when it was not.
+    chunks: usize,
+    source: Option<&'a str>,
+    model: &'a str,
+    timestamp: &'a str,
+    latency_ms: u128,
+}
+
+/// Label, compound, scores and stars of `report`, the part every JSON
+/// description of an analysis has in common.
+pub fn sentiment_fields(report: &SentimentReport) -> Map<String, Value> {
+    let fields = json!({
+        "label": report.dominant().to_string().to_lowercase(),
+        "compound": report.compound(),
+        "scores": {
+            "negative": report.negative_score,
+            "neutral": report.neutral_score,
+            "positive": report.positive_score,
+        },
+        "stars": report.stars,
+    });
+    match fields {
+        Value::Object(fields) => fields,
+        _ => unreachable!("built as an object"),
+    }
+}
+
+impl AnalysisRecord<'_> {
+    fn label(&self) -> String {
+        self.report.dominant().to_string().to_lowercase()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let chunks = self
+            .report
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let mut object = Map::new();
+                object.insert("text".to_string(), json!(chunk.text));
+                object.insert("tokens".to_string(), json!(chunk.tokens));
+                object.extend(sentiment_fields(&chunk.report));
+                Value::Object(object)
+            })
+            .collect::<Vec<_>>();
+        let mut record = Map::new();
+        record.insert("input".to_string(), json!(self.input));
+        record.extend(sentiment_fields(self.report));
+        record.insert("chunks".to_string(), Value::Array(chunks));
+        record.insert("source".to_string(), json!(self.source));
+        record.insert("model".to_string(), json!(self.model));
+        record.insert("timestamp".to_string(), json!(self.timestamp));
+        record.insert("latency_ms".to_string(), json!(self.latency.as_millis()));
+        Value::Object(record)
+    }
+
+    pub fn to_csv_row(&self) -> CsvRow<'_> {
+        CsvRow {
+            input: self.input,
+            label: self.label(),
+            compound: self.report.compound(),
+            negative: self.report.negative_score,
+            neutral: self.report.neutral_score,
+            positive: self.report.positive_score,
+            stars: self.report.stars,
+            chunks: self.report.chunks.len(),
+            source: self.source,
+            model: self.model,
+            timestamp: &self.timestamp,
+            latency_ms: self.latency.as_millis(),
+        }
+    }
+}
+
+impl ResultWriter {
+    /// Writes to `path` (replacing the file) or to stdout when there is none.
+    ///
+    /// Colour is never written to a file.
+    pub fn open(
+        format: OutputFormat,
+        renderer: Renderer,
+        model: String,
+        path: Option<&Path>,
+    ) -> Result<Self, String> {
+        let on_terminal = path.is_none() && io::stdout().is_terminal();
+        let (writer, renderer): (Box<dyn Write>, _) = match path {
+            Some(path) => (
+                Box::new(BufWriter::new(File::create(path).map_err(|e| {
+                    format!("Could not create {}: {e}", path.display())
+                })?)),
+                Renderer::new(true),
+            ),
+            None => (Box::new(io::stdout()), renderer),
+        };
+        Ok(ResultWriter {
+            format,
+            renderer,
+            model,
+            writer,
+            on_terminal,
+            above: None,
+            pending: String::new(),
+            written: 0,
+        })
+    }
+
+    /// Prints the following results above `bar`, rather than over it, when
+    /// both are drawn on the terminal.
+    pub fn print_above(&mut self, bar: &ProgressBar) {
+        self.above = (self.on_terminal && !bar.is_hidden()).then(|| bar.clone());
+    }
+
+    /// Writes the result of `input`, analyzed in `latency` (the duration of
+    /// the whole request when it carried several texts).
+    pub fn write(
+        &mut self,
+        input: &str,
+        report: &SentimentReport,
+        latency: Duration,
+        source: Option<&str>,
+    ) -> io::Result<()> {
+        let record = AnalysisRecord {
+            input,
+            report,
+            source,
+            model: &self.model,
+            timestamp: Utc::now().to_rfc3339(),
+            latency,
+        };
+        let mut out = Vec::new();
+        let first = self.written == 0;
+        match self.format {
+            OutputFormat::Human => {
+                if let Some(source) = source {
+                    writeln!(out, "[{source}]")?;
+                }
+                writeln!(out, "{}", self.renderer.render(input, report))?;
+            }
+            OutputFormat::Table => {
+                if first {
+                    writeln!(
+                        out,
+                        "{:<8} {:>8} {:>8} {:>8} {:>8} {:>8}  input",
+                        "label", "compound", "negative", "neutral", "positive", "ms"
+                    )?;
+                }
+                writeln!(
+                    out,
+                    "{:<8} {:>+8.3} {:>8.3} {:>8.3} {:>8.3} {:>8}  {}",
+                    record.label(),
+                    report.compound(),
+                    report.negative_score,
+                    report.neutral_score,
+                    report.positive_score,
+                    latency.as_millis(),
+                    snippet(input, TABLE_INPUT_WIDTH)
+                )?;
+            }
+            OutputFormat::Json => {
+                let separator = if first { "[\n" } else { ",\n" };
+                write!(out, "{separator}  {}", record.to_json())?;
+            }
+            OutputFormat::Ndjson => writeln!(out, "{}", record.to_json())?,
+            OutputFormat::Csv => {
+                let mut row = csv::WriterBuilder::new()
+                    .has_headers(first)
+                    .from_writer(Vec::new());
+                row.serialize(record.to_csv_row())?;
+                let row = row.into_inner().map_err(|e| e.into_error())?;
+                out.write_all(&row)?;
+            }
+        }
+        self.written += 1;
+        match &self.above {
+            Some(bar) => {
+                self.pending.push_str(&String::from_utf8_lossy(&out));
+                if let Some(end) = self.pending.rfind('\n') {
+                    bar.println(&self.pending[..end]);
+                    self.pending.drain(..=end);
+                }
+                Ok(())
+            }
+            None => {
+                self.writer.write_all(&out)?;
+                self.writer.flush()
+            }
+        }
+    }
+
+    /// Completes the output, which only matters for the JSON array.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.write_all(self.pending.as_bytes())?;
+        if self.format == OutputFormat::Json {
+            let closing = if self.written == 0 { "[]\n" } else { "\n]\n" };
+            self.writer.write_all(closing.as_bytes())?;
+        }
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{report, TempDir};
+
+    /// What a run writing `results` in `format` leaves in its output file.
+    fn written(format: OutputFormat, results: &[(&str, SentimentReport)]) -> String {
+        let dir = TempDir::new("output");
+        let path = dir.join("results");
+        let mut output =
+            ResultWriter::open(format, Renderer::new(true), "model".into(), Some(&path)).unwrap();
+        for (input, report) in results {
+            output
+                .write(input, report, Duration::from_millis(12), None)
+                .unwrap();
+        }
+        output.finish().unwrap();
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    fn two_results() -> Vec<(&'static str, SentimentReport)> {
+        vec![
+            ("I love it", report(0.1, 0.2, 0.7)),
+            ("Say \"no\", twice\nand leave", report(0.8, 0.1, 0.1)),
+        ]
+    }
+
+    #[test]
+    fn json_is_a_single_array() {
+        let json = written(OutputFormat::Json, &two_results());
+        let records: Vec<Value> = serde_json::from_str(&json).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["input"], "I love it");
+        assert_eq!(records[0]["label"], "positive");
+        assert_eq!(records[1]["label"], "negative");
+        assert_eq!(records[1]["model"], "model");
+        assert_eq!(records[1]["latency_ms"], 12);
+
+        assert_eq!(written(OutputFormat::Json, &[]), "[]\n");
+    }
+
+    #[test]
+    fn ndjson_has_one_object_per_line() {
+        let ndjson = written(OutputFormat::Ndjson, &two_results());
+        let lines = ndjson.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2, "{ndjson}");
+        for (line, (input, _)) in lines.iter().zip(two_results()) {
+            let record: Value = serde_json::from_str(line).unwrap();
+            assert_eq!(record["input"], input);
+        }
+        assert_eq!(written(OutputFormat::Ndjson, &[]), "");
+    }
+
+    #[test]
+    fn csv_has_one_header_and_quoted_fields() {
+        let csv = written(OutputFormat::Csv, &two_results());
+        assert_eq!(csv.matches("input,label,compound").count(), 1, "{csv}");
+        assert!(
+            csv.contains("\"Say \"\"no\"\", twice\nand leave\""),
+            "{csv}"
+        );
+
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        assert_eq!(
+            reader.headers().unwrap(),
+            vec![
+                "input",
+                "label",
+                "compound",
+                "negative",
+                "neutral",
+                "positive",
+                "stars",
+                "chunks",
+                "source",
+                "model",
+                "timestamp",
+                "latency_ms"
+            ]
+        );
+        let records = reader.records().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(&records[1][0], "Say \"no\", twice\nand leave");
+        assert_eq!(&records[1][1], "negative");
+    }
+
+    #[test]
+    fn table_columns_line_up() {
+        let table = written(
+            OutputFormat::Table,
+            &[
+                ("I love it", report(0.1, 0.2, 0.7)),
+                ("meh", report(0.2, 0.6, 0.2)),
+                ("Awful", report(0.9, 0.05, 0.05)),
+            ],
+        );
+        let lines = table.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4, "{table}");
+        let header = lines[0];
+        for (line, input) in lines[1..].iter().zip(["I love it", "meh", "Awful"]) {
+            assert_eq!(line.len(), header.len() - "input".len() + input.len());
+            assert_eq!(&line[header.find("input").unwrap()..], input);
+            // Every column ends where its header does.
+            for column in ["compound", "negative", "neutral", "positive", "ms"] {
+                let end = header.find(column).unwrap() + column.len();
+                assert_eq!(&line[end..end + 1], " ", "{column} in {line:?}");
+                assert_ne!(&line[end - 1..end], " ", "{column} in {line:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use crate::backend::{BackendError, SentimentBackend};
+use crate::report::SentimentReport;
+use futures::stream::{self, StreamExt};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Whether results come out in the order texts went in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    Ordered,
+    /// As soon as they are ready, which keeps a slow request from holding
+    /// back the ones behind it.
+    Unordered,
+}
+
+/// Runs many analyses at once on a tokio runtime.
+///
+/// Texts are pulled from the source on a dedicated blocking task and handed
+/// over through a bounded channel, so a source never gets far ahead of the
+/// requests in flight (backpressure). At most `concurrency` chunks of
+/// `chunk_size` texts are being analyzed at any time, each chunk going
+/// through `SentimentBackend::analyze_batch`.
+///
+/// The backends make blocking requests (`reqwest::blocking`), so every chunk
+/// runs on the blocking thread pool of the runtime: the concurrency comes from
+/// threads, the async side only schedules the chunks and bounds their number.
+pub struct Pipeline {
+    backend: Arc<dyn SentimentBackend>,
+    concurrency: usize,
+    chunk_size: usize,
+    delivery: Delivery,
+    /// Built once and reused by every run, `None` if it could not be started.
+    runtime: Option<tokio::runtime::Runtime>,
+}
+
+type ChunkResults = (
+    Vec<(String, Result<SentimentReport, BackendError>)>,
+    Duration,
+);
+
+impl Pipeline {
+    pub fn new(
+        backend: Arc<dyn SentimentBackend>,
+        concurrency: usize,
+        chunk_size: usize,
+        delivery: Delivery,
+    ) -> Self {
+        let runtime = match tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => Some(runtime),
+            Err(err) => {
+                // Without a runtime there is no concurrency to be had, analyze in place.
+                eprintln!("Could not start the async runtime ({err}), analyzing sequentially");
+                None
+            }
+        };
+        Pipeline {
+            backend,
+            concurrency: concurrency.max(1),
+            chunk_size: chunk_size.max(1),
+            delivery,
+            runtime,
+        }
+    }
+
+    pub fn backend(&self) -> &dyn SentimentBackend {
+        self.backend.as_ref()
+    }
+
+    /// Analyzes every text of `source`, calling `on_result` with the index of
+    /// the text in the source, the text, its result and the time the request
+    /// took (shared by every text of a chunk).
+    ///
+    /// Returns once the source is exhausted and every result was delivered.
+    pub fn run<I, F>(&self, source: I, mut on_result: F)
+    where
+        I: Iterator<Item = String> + Send + 'static,
+        F: FnMut(usize, String, Result<SentimentReport, BackendError>, Duration),
+    {
+        let Some(runtime) = &self.runtime else {
+            for (index, text) in source.enumerate() {
+                let started = Instant::now();
+                let result = self.backend.analyze(&text);
+                on_result(index, text, result, started.elapsed());
+            }
+            return;
+        };
+
+        runtime.block_on(async {
+            let (sender, receiver) = mpsc::channel::<(usize, Vec<String>)>(self.concurrency);
+            let chunk_size = self.chunk_size;
+            let producer = tokio::task::spawn_blocking(move || {
+                let mut source = source.peekable();
+                let mut first_index = 0;
+                while source.peek().is_some() {
+                    let chunk = source.by_ref().take(chunk_size).collect::<Vec<_>>();
+                    let chunk_len = chunk.len();
+                    // Blocks while `concurrency` chunks are already waiting.
+                    if sender.blocking_send((first_index, chunk)).is_err() {
+                        break;
+                    }
+                    first_index += chunk_len;
+                }
+            });
+
+            let chunks = stream::unfold(receiver, |mut receiver| async move {
+                receiver.recv().await.map(|chunk| (chunk, receiver))
+            });
+            let analyses = chunks.map(|(first_index, texts)| {
+                let backend = Arc::clone(&self.backend);
+                async move {
+                    let analyzed = tokio::task::spawn_blocking(move || {
+                        let started = Instant::now();
+                        // A panicking backend still owes an answer for every
+                        // text of the chunk, or they would silently go missing:
+                        // the texts are analyzed again one by one, so that only
+                        // the one at fault fails.
+                        let results =
+                            panic::catch_unwind(AssertUnwindSafe(|| backend.analyze_batch(&texts)))
+                                .unwrap_or_else(|_| {
+                                    texts
+                                        .iter()
+                                        .map(|text| analyze_alone(&*backend, text))
+                                        .collect()
+                                });
+                        let analyzed: ChunkResults =
+                            (texts.into_iter().zip(results).collect(), started.elapsed());
+                        analyzed
+                    })
+                    .await
+                    .expect("panics are caught and blocking tasks are never cancelled");
+                    (first_index, analyzed)
+                }
+            });
+
+            let mut deliver = |(first_index, (results, latency)): (usize, ChunkResults)| {
+                for (offset, (text, result)) in results.into_iter().enumerate() {
+                    on_result(first_index + offset, text, result, latency);
+                }
+            };
+            match self.delivery {
+                Delivery::Ordered => {
+                    let mut results = std::pin::pin!(analyses.buffered(self.concurrency));
+                    while let Some(chunk) = results.next().await {
+                        deliver(chunk);
+                    }
+                }
+                Delivery::Unordered => {
+                    let mut results = std::pin::pin!(analyses.buffer_unordered(self.concurrency));
+                    while let Some(chunk) = results.next().await {
+                        deliver(chunk);
+                    }
+                }
+            }
+
+            if let Err(err) = producer.await {
+                eprintln!("Reading the texts to analyze failed: {err}");
+            }
+        });
+    }
+}
+
+/// `backend.analyze`, with a panic reported as the error of `text`.
+fn analyze_alone(
+    backend: &dyn SentimentBackend,
+    text: &str,
+) -> Result<SentimentReport, BackendError> {
+    panic::catch_unwind(AssertUnwindSafe(|| backend.analyze(text)))
+        .unwrap_or_else(|_| Err(BackendError::Transport("the analysis panicked".to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{report, ScriptedBackend};
+
+    fn analyze(text: &str) -> Result<SentimentReport, BackendError> {
+        match text {
+            "boom" => panic!("scripted panic"),
+            "slow" => std::thread::sleep(Duration::from_millis(300)),
+            _ => {}
+        }
+        Ok(report(0.1, 0.2, 0.7))
+    }
+
+    /// Index, text and error (if any) of each result, in delivery order.
+    fn run(delivery: Delivery, texts: &[&str]) -> Vec<(usize, String, Option<String>)> {
+        let pipeline = Pipeline::new(Arc::new(ScriptedBackend(analyze)), 2, 2, delivery);
+        let texts = texts
+            .iter()
+            .map(|text| text.to_string())
+            .collect::<Vec<_>>();
+        let mut results = Vec::new();
+        pipeline.run(texts.into_iter(), |index, text, result, _| {
+            results.push((index, text, result.err().map(|err| err.to_string())))
+        });
+        results
+    }
+
+    #[test]
+    fn ordered_delivery_follows_the_source_even_when_a_chunk_is_slow() {
+        let results = run(Delivery::Ordered, &["slow", "b", "c", "d", "e"]);
+        let delivered = results.iter().map(|(index, ..)| *index).collect::<Vec<_>>();
+        assert_eq!(delivered, [0, 1, 2, 3, 4]);
+        assert!(results.iter().all(|(_, _, error)| error.is_none()));
+    }
+
+    #[test]
+    fn unordered_delivery_does_not_wait_for_a_slow_chunk() {
+        let results = run(Delivery::Unordered, &["slow", "b", "c", "d"]);
+        let delivered = results.iter().map(|(index, ..)| *index).collect::<Vec<_>>();
+        assert_eq!(delivered, [2, 3, 0, 1]);
+    }
+
+    #[test]
+    fn a_panicking_text_fails_alone_and_the_run_goes_on() {
+        for delivery in [Delivery::Ordered, Delivery::Unordered] {
+            let mut results = run(delivery, &["a", "boom", "c", "d", "e"]);
+            results.sort();
+            let failed = results
+                .iter()
+                .filter_map(|(index, _, error)| error.as_ref().map(|error| (*index, error)))
+                .collect::<Vec<_>>();
+            assert_eq!(failed.len(), 1, "{results:?}");
+            assert_eq!(failed[0].0, 1);
+            assert!(failed[0].1.contains("panicked"), "{}", failed[0].1);
+            assert_eq!(results.len(), 5);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::backend::SentimentBackend;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::io::IsTerminal;
+use std::sync::Mutex;
+
+/// Milliseconds between two frames of a spinner.
+const SPINNER_TICK_MS: u64 = 80;
+
+/// Spinner currently drawn, so that a prompt can take the line over.
+static ACTIVE_SPINNER: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Progress bar currently drawn, so that messages can be printed above it.
+static ACTIVE_BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Whether progress can be drawn: stderr must be a terminal.
+///
+/// Results printed to the same terminal go above the bar, see
+/// `ResultWriter::print_above`.
+pub fn visible(no_progress: bool) -> bool {
+    !no_progress && std::io::stderr().is_terminal()
+}
+
+/// Prints `message` on stderr, above the spinner or the progress bar being
+/// drawn if any, so that it does not tear them apart.
+pub fn note(message: &str) {
+    let drawn = active(&ACTIVE_SPINNER)
+        .clone()
+        .or_else(|| active(&ACTIVE_BAR).clone());
+    match drawn {
+        Some(bar) if !bar.is_hidden() => bar.println(message),
+        _ => eprintln!("{message}"),
+    }
+}
+
+/// Progress of a run over many texts, drawn on stderr: processed/total,
+/// throughput, errors, cache hit rate and ETA.
+pub struct Progress<'a> {
+    bar: ProgressBar,
+    backend: &'a dyn SentimentBackend,
+    errors: u64,
+}
+
+impl<'a> Progress<'a> {
+    /// Without a `total`, only the count and the throughput are shown.
+    pub fn new(total: Option<u64>, backend: &'a dyn SentimentBackend, visible: bool) -> Self {
+        let bar = match (visible, total) {
+            (false, _) => ProgressBar::hidden(),
+            (true, Some(total)) => ProgressBar::new(total).with_style(bar_style()),
+            (true, None) => ProgressBar::new_spinner().with_style(
+                ProgressStyle::default_spinner()
+                    .template("{spinner} [{elapsed_precise}] {pos} analyzed ({per_sec}) {msg}"),
+            ),
+        };
+        if !bar.is_hidden() {
+            *active(&ACTIVE_BAR) = Some(bar.clone());
+        }
+        Progress {
+            bar,
+            backend,
+            errors: 0,
+        }
+    }
+
+    /// The bar itself, for results to be printed above it.
+    pub fn bar(&self) -> &ProgressBar {
+        &self.bar
+    }
+
+    /// Turns a bar started without a total into a full one.
+    pub fn set_total(&self, total: u64) {
+        self.bar.set_length(total);
+        self.bar.set_style(bar_style());
+    }
+
+    /// Counts one more analyzed text.
+    pub fn success(&mut self) {
+        self.bar.set_message(self.status());
+        self.bar.inc(1);
+    }
+
+    /// Counts one more failed text, printing `message` above the bar.
+    pub fn failure(&mut self, message: Option<&str>) {
+        self.errors += 1;
+        match message {
+            Some(message) if self.bar.is_hidden() => eprintln!("{message}"),
+            Some(message) => self.bar.println(message),
+            None => {}
+        }
+        self.bar.set_message(self.status());
+        self.bar.inc(1);
+    }
+
+    fn status(&self) -> String {
+        let mut status = format!("{} errors", self.errors);
+        if let Some(hit_rate) = self.backend.cache_hit_rate() {
+            status.push_str(&format!(", {:.0}% cached", hit_rate * 100.0));
+        }
+        status
+    }
+
+    /// Leaves the final state of the bar on screen.
+    pub fn finish(&self) {
+        self.bar.finish();
+    }
+}
+
+impl Drop for Progress<'_> {
+    fn drop(&mut self) {
+        if !self.bar.is_hidden() {
+            active(&ACTIVE_BAR).take();
+        }
+    }
+}
+
+fn bar_style() -> ProgressStyle {
+    ProgressStyle::default_bar()
+        .template("{spinner} [{elapsed_precise}] {bar:30} {pos}/{len} ({per_sec}, ETA {eta}) {msg}")
+        .progress_chars("=> ")
+}
+
+/// Spinner drawn on stderr while a single request is in flight, cleared when
+/// dropped.
+pub struct Spinner;
+
+impl Spinner {
+    pub fn start(message: &str) -> Self {
+        if std::io::stderr().is_terminal() {
+            let spinner = ProgressBar::new_spinner().with_message(message.to_string());
+            spinner.enable_steady_tick(SPINNER_TICK_MS);
+            *active(&ACTIVE_SPINNER) = Some(spinner);
+        }
+        Spinner
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        clear_spinner();
+    }
+}
+
+/// Removes the spinner, if any, before something else is drawn on its line
+/// (a prompt in the middle of a request).
+pub fn clear_spinner() {
+    if let Some(spinner) = active(&ACTIVE_SPINNER).take() {
+        spinner.finish_and_clear();
+    }
+}
+
+fn active(
+    drawn: &'static Mutex<Option<ProgressBar>>,
+) -> std::sync::MutexGuard<'static, Option<ProgressBar>> {
+    drawn
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,221 @@
+use crate::report::{Sentiment, SentimentReport};
+use colorize::AnsiColor;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const BAR_WIDTH: usize = 20;
+const SNIPPET_LENGTH: usize = 60;
+
+/// Whether messages printed on stderr are coloured, see `set_message_color`.
+static MESSAGE_COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Decides whether messages on stderr are coloured, under the same rules as
+/// the `Renderer` but for stderr being a terminal.
+pub fn set_message_color(no_color_flag: bool) {
+    MESSAGE_COLOR.store(
+        !no_color_flag && !no_color_env() && std::io::stderr().is_terminal(),
+        Ordering::Relaxed,
+    );
+}
+
+fn no_color_env() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+/// Colours of the messages printed on stderr, left out unless
+/// `set_message_color` turned them on.
+pub trait Tint {
+    fn red(self) -> String;
+    fn yellow(self) -> String;
+    fn green(self) -> String;
+}
+
+impl<T: Into<String>> Tint for T {
+    fn red(self) -> String {
+        tint(self.into(), AnsiColor::red)
+    }
+
+    fn yellow(self) -> String {
+        tint(self.into(), AnsiColor::yellow)
+    }
+
+    fn green(self) -> String {
+        tint(self.into(), AnsiColor::green)
+    }
+}
+
+fn tint(text: String, color: fn(String) -> String) -> String {
+    if MESSAGE_COLOR.load(Ordering::Relaxed) {
+        color(text)
+    } else {
+        text
+    }
+}
+
+/// Turns a `SentimentReport` into something a human wants to read.
+///
+/// Colour is used when stdout is a terminal, unless it was disabled with
+/// `--no-color` or through the `NO_COLOR` environment variable
+/// (https://no-color.org). Otherwise the output is plain ASCII so it can be
+/// grepped out of logs, `analyze x > log` included.
+#[derive(Debug, Clone, Copy)]
+pub struct Renderer {
+    color: bool,
+}
+
+impl Renderer {
+    pub fn new(no_color_flag: bool) -> Self {
+        Renderer {
+            color: !no_color_flag && !no_color_env() && std::io::stdout().is_terminal(),
+        }
+    }
+
+    /// A message printed on stdout telling that something went well.
+    pub fn success(&self, message: &str) -> String {
+        if self.color {
+            AnsiColor::green(message.to_string())
+        } else {
+            message.to_string()
+        }
+    }
+
+    pub fn render(&self, text: &str, report: &SentimentReport) -> String {
+        let dominant = report.dominant();
+        let stars = report
+            .stars
+            .map(|stars| format!("  stars {stars:.1}/5"))
+            .unwrap_or_default();
+        let mut output = format!(
+            "{}  compound {:+.3}{}\n  \"{}\"\n",
+            self.paint(dominant, &dominant.to_string().to_uppercase()),
+            report.compound(),
+            stars,
+            snippet(text, SNIPPET_LENGTH)
+        );
+        for (sentiment, score) in [
+            (Sentiment::Negative, report.negative_score),
+            (Sentiment::Neutral, report.neutral_score),
+            (Sentiment::Positive, report.positive_score),
+        ] {
+            output.push_str(&format!(
+                "  {:<8} {} {:.3}\n",
+                sentiment.to_string().to_lowercase(),
+                self.paint(sentiment, &self.bar(score)),
+                score
+            ));
+        }
+        if !report.chunks.is_empty() {
+            output.push_str(&format!("  {} chunks\n", report.chunks.len()));
+            for (index, chunk) in report.chunks.iter().enumerate() {
+                let dominant = chunk.report.dominant();
+                output.push_str(&format!(
+                    "  {:>3}. {} {:+.3}  \"{}\"\n",
+                    index + 1,
+                    // Padded before painting, or the escape codes would count as width.
+                    self.paint(
+                        dominant,
+                        &format!("{:<8}", dominant.to_string().to_lowercase())
+                    ),
+                    chunk.report.compound(),
+                    snippet(&chunk.text, SNIPPET_LENGTH)
+                ));
+            }
+        }
+        output
+    }
+
+    fn bar(&self, score: f64) -> String {
+        let filled = ((score.clamp(0.0, 1.0) * BAR_WIDTH as f64).round()) as usize;
+        let (full, empty) = if self.color {
+            ('█', '░')
+        } else {
+            ('#', '.')
+        };
+        std::iter::repeat_n(full, filled)
+            .chain(std::iter::repeat_n(empty, BAR_WIDTH - filled))
+            .collect()
+    }
+
+    fn paint(&self, sentiment: Sentiment, text: &str) -> String {
+        if !self.color {
+            return text.to_string();
+        }
+        let text = text.to_string();
+        match sentiment {
+            Sentiment::Negative => AnsiColor::red(text),
+            Sentiment::Neutral => AnsiColor::yellow(text),
+            Sentiment::Positive => AnsiColor::green(text),
+        }
+    }
+}
+
+/// First `length` characters of the input on a single line.
+pub fn snippet(text: &str, length: usize) -> String {
+    let single_line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if single_line.chars().count() > length {
+        let truncated: String = single_line.chars().take(length.saturating_sub(3)).collect();
+        format!("{truncated}...")
+    } else {
+        single_line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::report;
+
+    const PLAIN: Renderer = Renderer { color: false };
+    const COLOURED: Renderer = Renderer { color: true };
+
+    #[test]
+    fn bars_are_filled_in_proportion_to_the_score() {
+        assert_eq!(PLAIN.bar(0.0), "....................");
+        assert_eq!(PLAIN.bar(0.5), "##########..........");
+        assert_eq!(PLAIN.bar(0.26), "#####...............");
+        assert_eq!(PLAIN.bar(1.0), "####################");
+        // Scores out of range do not overflow the bar.
+        assert_eq!(PLAIN.bar(1.5), PLAIN.bar(1.0));
+        assert_eq!(PLAIN.bar(-0.5), PLAIN.bar(0.0));
+        assert_eq!(COLOURED.bar(0.5), "██████████░░░░░░░░░░");
+    }
+
+    #[test]
+    fn plain_output_has_no_escape_codes() {
+        let rendered = PLAIN.render("What a  lovely\nday", &report(0.1, 0.2, 0.7));
+        assert_eq!(
+            rendered,
+            "POSITIVE  compound +0.600\n  \"What a lovely day\"\n  \
+             negative ##.................. 0.100\n  \
+             neutral  ####................ 0.200\n  \
+             positive ##############...... 0.700\n"
+        );
+    }
+
+    #[test]
+    fn coloured_output_paints_the_label_and_the_bars() {
+        let rendered = COLOURED.render("Awful", &report(0.8, 0.1, 0.1));
+        assert!(rendered.starts_with(&AnsiColor::red("NEGATIVE".to_string())));
+        assert!(rendered.contains(&AnsiColor::yellow(COLOURED.bar(0.1))));
+        assert!(rendered.contains(&AnsiColor::green(COLOURED.bar(0.1))));
+    }
+
+    #[test]
+    fn snippets_are_single_lines_of_bounded_length() {
+        assert_eq!(snippet("short", 10), "short");
+        assert_eq!(snippet(" two\n\tlines ", 10), "two lines");
+        assert_eq!(snippet("exactly ten", 11), "exactly ten");
+        assert_eq!(snippet("a rather long sentence", 10), "a rathe...");
+        assert_eq!(snippet("ééééééééééé", 5), "éé...");
+    }
+
+    #[test]
+    fn messages_are_tinted_only_when_turned_on() {
+        MESSAGE_COLOR.store(true, Ordering::Relaxed);
+        let tinted = Tint::red("Failed");
+        MESSAGE_COLOR.store(false, Ordering::Relaxed);
+        assert_eq!(tinted, AnsiColor::red("Failed".to_string()));
+        assert_eq!(Tint::red("Failed"), "Failed");
+        assert_eq!(Tint::yellow(format!("{} left", 2)), "2 left");
+    }
+}
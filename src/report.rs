@@ -0,0 +1,220 @@
+use crate::labels::LabelMapping;
+use serde::Deserialize;
+use std::fmt;
+use strum_macros::Display;
+
+/// The three sentiment classes a report can be dominated by.
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sentiment {
+    Negative,
+    Neutral,
+    Positive,
+}
+
+/// A single `{"label": ..., "score": ...}` entry as returned by the Inference API.
+#[derive(Deserialize, Debug, Clone)]
+pub struct LabelScore {
+    pub label: String,
+    pub score: f64,
+}
+
+/// Typed result of a sentiment analysis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentimentReport {
+    pub neutral_score: f64,
+    pub positive_score: f64,
+    pub negative_score: f64,
+    /// Expected rating, for models scoring on a 1 to 5 star scale.
+    pub stars: Option<f64>,
+    /// Scores of each chunk when the text was too long to be analyzed at
+    /// once, empty otherwise.
+    pub chunks: Vec<ChunkScore>,
+}
+
+/// Score of one sentence aligned chunk of a long text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkScore {
+    pub text: String,
+    /// Estimated number of tokens of `text`.
+    pub tokens: usize,
+    pub report: SentimentReport,
+}
+
+#[derive(Debug, Clone)]
+pub enum ReportError {
+    /// The body was not the `[[{"label","score"}]]` shape we expect.
+    Malformed(String),
+    /// The API answered with no label at all.
+    Empty,
+    /// A label the model's mapping does not know about.
+    UnexpectedLabel(String),
+    /// The same label was reported twice.
+    DuplicateLabel(String),
+    /// A label of the model's mapping is absent from the response.
+    MissingLabel(String),
+}
+
+impl fmt::Display for ReportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportError::Malformed(err) => write!(f, "Malformed sentiment response: {err}"),
+            ReportError::Empty => write!(f, "Sentiment response did not contain any label"),
+            ReportError::UnexpectedLabel(label) => {
+                write!(f, "Sentiment response contained unexpected label '{label}'")
+            }
+            ReportError::DuplicateLabel(label) => {
+                write!(f, "Sentiment response contained label '{label}' twice")
+            }
+            ReportError::MissingLabel(label) => {
+                write!(f, "Sentiment response is missing label '{label}'")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReportError {}
+
+impl SentimentReport {
+    /// The class with the highest score.
+    pub fn dominant(&self) -> Sentiment {
+        let mut dominant = (Sentiment::Neutral, self.neutral_score);
+        for candidate in [
+            (Sentiment::Positive, self.positive_score),
+            (Sentiment::Negative, self.negative_score),
+        ] {
+            if candidate.1 > dominant.1 {
+                dominant = candidate;
+            }
+        }
+        dominant.0
+    }
+
+    /// Single number summary in `[-1, 1]`: positive minus negative mass.
+    pub fn compound(&self) -> f64 {
+        (self.positive_score - self.negative_score).clamp(-1.0, 1.0)
+    }
+}
+
+/// Parses the raw body returned for a single input.
+///
+/// The Inference API wraps the label list of each input in an outer array,
+/// so a single input comes back as `[[{"label": ..., "score": ...}, ...]]`.
+pub fn parse_sentiment_response(
+    body: &str,
+    label_mapping: &LabelMapping,
+) -> Result<SentimentReport, ReportError> {
+    let outer: Vec<Vec<LabelScore>> =
+        serde_json::from_str(body).map_err(|e| ReportError::Malformed(e.to_string()))?;
+
+    match outer.as_slice() {
+        [label_scores] => label_mapping.apply(label_scores),
+        [] => Err(ReportError::Empty),
+        _ => Err(ReportError::Malformed(format!(
+            "expected results for one input, got {}",
+            outer.len()
+        ))),
+    }
+}
+
+/// Parses the raw body returned for several inputs, one result per input.
+///
+/// The body itself being malformed fails the whole batch, whereas a label set
+/// that cannot be mapped only fails its own input.
+pub fn parse_batch_response(
+    body: &str,
+    label_mapping: &LabelMapping,
+) -> Result<Vec<Result<SentimentReport, ReportError>>, ReportError> {
+    let outer: Vec<Vec<LabelScore>> =
+        serde_json::from_str(body).map_err(|e| ReportError::Malformed(e.to_string()))?;
+
+    Ok(outer
+        .iter()
+        .map(|label_scores| label_mapping.apply(label_scores))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn three_way() -> LabelMapping {
+        LabelMapping::for_model("cardiffnlp/twitter-roberta-base-sentiment-latest")
+    }
+
+    #[test]
+    fn a_single_input_is_parsed_into_a_report() {
+        let body = r#"[[{"label":"positive","score":0.7},{"label":"neutral","score":0.2},{"label":"negative","score":0.1}]]"#;
+        let report = parse_sentiment_response(body, &three_way()).unwrap();
+        assert_eq!(report.positive_score, 0.7);
+        assert_eq!(report.neutral_score, 0.2);
+        assert_eq!(report.negative_score, 0.1);
+        assert_eq!(report.dominant(), Sentiment::Positive);
+        assert!((report.compound() - 0.6).abs() < 1e-9);
+    }
+
+    #[test]
+    fn malformed_bodies_are_rejected() {
+        for body in [
+            "not json",
+            r#"{"error":"Model is loading"}"#,
+            r#"[{"label":"positive","score":1.0}]"#,
+        ] {
+            assert!(
+                matches!(
+                    parse_sentiment_response(body, &three_way()),
+                    Err(ReportError::Malformed(_))
+                ),
+                "{body}"
+            );
+        }
+    }
+
+    #[test]
+    fn a_single_input_needs_exactly_one_result() {
+        assert!(matches!(
+            parse_sentiment_response("[]", &three_way()),
+            Err(ReportError::Empty)
+        ));
+        assert!(matches!(
+            parse_sentiment_response("[[]]", &three_way()),
+            Err(ReportError::Empty)
+        ));
+        let two = r#"[[{"label":"positive","score":1.0}],[{"label":"negative","score":1.0}]]"#;
+        assert!(matches!(
+            parse_sentiment_response(two, &LabelMapping::generic()),
+            Err(ReportError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn a_bad_label_set_only_fails_its_own_input() {
+        let body = r#"[
+            [{"label":"positive","score":0.9},{"label":"neutral","score":0.05},{"label":"negative","score":0.05}],
+            [{"label":"joy","score":1.0}]
+        ]"#;
+        let results = parse_batch_response(body, &three_way()).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap().dominant(), Sentiment::Positive);
+        assert!(matches!(&results[1], Err(ReportError::UnexpectedLabel(label)) if label == "joy"));
+    }
+
+    #[test]
+    fn a_malformed_batch_fails_as_a_whole() {
+        assert!(matches!(
+            parse_batch_response(r#"{"error":"overloaded"}"#, &three_way()),
+            Err(ReportError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn ties_are_reported_as_neutral() {
+        let report = SentimentReport {
+            neutral_score: 0.4,
+            positive_score: 0.4,
+            negative_score: 0.2,
+            stars: None,
+            chunks: Vec::new(),
+        };
+        assert_eq!(report.dominant(), Sentiment::Neutral);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::output::AnalysisRecord;
+use crate::report::SentimentReport;
+use chrono::{DateTime, Utc};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+use strum_macros::Display;
+
+/// Every text analyzed during an interactive session, in order.
+#[derive(Debug)]
+pub struct Session {
+    /// Model every text was analyzed with.
+    model: String,
+    entries: Vec<SessionEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionEntry {
+    pub text: String,
+    pub report: SentimentReport,
+    pub analyzed_at: DateTime<Utc>,
+    pub latency: Duration,
+}
+
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[strum(serialize = "CSV")]
+    Csv,
+    #[strum(serialize = "JSON")]
+    Json,
+    #[strum(serialize = "NDJSON (one JSON object per line)")]
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+impl Session {
+    pub fn new(model: String) -> Self {
+        Session {
+            model,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, text: &str, report: &SentimentReport, latency: Duration) {
+        self.entries.push(SessionEntry {
+            text: text.to_string(),
+            report: report.clone(),
+            analyzed_at: Utc::now(),
+            latency,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Writes the whole session to `path`, replacing the file if it exists.
+    ///
+    /// The records are those of `--format`, so that a session export reads
+    /// like the output of a run.
+    pub fn export(&self, path: &Path, format: ExportFormat) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        let records = self.entries.iter().map(|entry| AnalysisRecord {
+            input: &entry.text,
+            report: &entry.report,
+            source: None,
+            model: &self.model,
+            timestamp: entry.analyzed_at.to_rfc3339(),
+            latency: entry.latency,
+        });
+        match format {
+            ExportFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(file);
+                for record in records {
+                    writer
+                        .serialize(record.to_csv_row())
+                        .map_err(|e| e.to_string())?;
+                }
+                writer.flush().map_err(|e| e.to_string())
+            }
+            ExportFormat::Json => {
+                let mut writer = BufWriter::new(file);
+                let records = records.map(|record| record.to_json()).collect::<Vec<_>>();
+                serde_json::to_writer_pretty(&mut writer, &records).map_err(|e| e.to_string())?;
+                writer.flush().map_err(|e| e.to_string())
+            }
+            ExportFormat::Ndjson => {
+                let mut writer = BufWriter::new(file);
+                for record in records {
+                    serde_json::to_writer(&mut writer, &record.to_json())
+                        .map_err(|e| e.to_string())?;
+                    writeln!(writer).map_err(|e| e.to_string())?;
+                }
+                writer.flush().map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{report, TempDir};
+
+    #[test]
+    fn exports_use_the_records_of_the_result_output() {
+        let dir = TempDir::new("session");
+        let mut session = Session::new("some/model".to_string());
+        session.record(
+            "fine day",
+            &report(0.1, 0.2, 0.7),
+            Duration::from_millis(42),
+        );
+
+        let path = dir.join("session.ndjson");
+        session.export(&path, ExportFormat::Ndjson).unwrap();
+        let line: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let keys = line.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [
+                "input",
+                "label",
+                "compound",
+                "scores",
+                "stars",
+                "chunks",
+                "source",
+                "model",
+                "timestamp",
+                "latency_ms"
+            ]
+        );
+        assert_eq!(line["scores"]["positive"], 0.7);
+        assert_eq!(line["model"], "some/model");
+        assert_eq!(line["latency_ms"], 42);
+
+        let path = dir.join("session.csv");
+        session.export(&path, ExportFormat::Csv).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        assert!(csv.starts_with(
+            "input,label,compound,negative,neutral,positive,stars,chunks,source,model,timestamp,latency_ms\n"
+        ), "{csv}");
+    }
+}
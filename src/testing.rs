@@ -0,0 +1,120 @@
+//! Helpers shared by the unit tests.
+
+use crate::backend::{BackendError, SentimentBackend};
+use crate::report::SentimentReport;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// Directory under the system temporary directory, removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "sentiment-analyzer-{name}-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).expect("temporary directory");
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Held by the tests that change environment variables, which are shared by
+/// the whole process.
+pub fn lock_env() -> MutexGuard<'static, ()> {
+    static ENV: Mutex<()> = Mutex::new(());
+    ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub fn report(negative: f64, neutral: f64, positive: f64) -> SentimentReport {
+    SentimentReport {
+        neutral_score: neutral,
+        positive_score: positive,
+        negative_score: negative,
+        stars: None,
+        chunks: Vec::new(),
+    }
+}
+
+/// Backend answering with whatever its function returns for a text.
+pub struct ScriptedBackend(pub fn(&str) -> Result<SentimentReport, BackendError>);
+
+impl SentimentBackend for ScriptedBackend {
+    fn describe(&self) -> String {
+        "scripted".to_string()
+    }
+
+    fn model(&self) -> String {
+        "scripted".to_string()
+    }
+
+    fn labels(&self) -> Vec<String> {
+        vec!["negative".into(), "neutral".into(), "positive".into()]
+    }
+
+    fn analyze(&self, text: &str) -> Result<SentimentReport, BackendError> {
+        (self.0)(text)
+    }
+}
+
+/// Serves `responses` (status and body) to the next requests made to the
+/// returned base URL, one response per connection, in order.
+pub fn serve(responses: Vec<(u16, String)>) -> String {
+    serve_with_headers(
+        responses
+            .into_iter()
+            .map(|(status, body)| (status, "", body))
+            .collect(),
+    )
+}
+
+/// `serve`, with extra header lines (`"Retry-After: 1\r\n"`) in each response.
+pub fn serve_with_headers(responses: Vec<(u16, &'static str, String)>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("local listener");
+    let address = listener.local_addr().expect("local address");
+    std::thread::spawn(move || {
+        for (status, headers, body) in responses {
+            let Ok((mut stream, _)) = listener.accept() else {
+                return;
+            };
+            // The request itself does not matter, only its end does.
+            let mut reader = BufReader::new(&mut stream);
+            let mut line = String::new();
+            let mut content_length = 0;
+            while reader.read_line(&mut line).is_ok_and(|read| read > 2) {
+                if let Some(length) = line.to_lowercase().strip_prefix("content-length:") {
+                    content_length = length.trim().parse().unwrap_or(0);
+                }
+                line.clear();
+            }
+            // Read the body too, or closing the socket could reset the connection.
+            let mut request_body = vec![0; content_length];
+            let _ = reader.read_exact(&mut request_body);
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {status} X\r\n{headers}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+        }
+    });
+    format!("http://{address}")
+}